@@ -0,0 +1,35 @@
+//! How long each algorithm takes to score a pair, and how much it allocates doing it.
+//! Run it with `cargo bench`.
+
+#[allow(dead_code)]
+#[path = "../tests/counting/mod.rs"]
+mod counting;
+
+use cheat_checker::similarity;
+use std::hint::black_box;
+use std::time::Instant;
+
+#[global_allocator]
+static ALLOCATOR: counting::Counting = counting::Counting;
+
+const ROUNDS: usize = 200;
+
+fn main() {
+    let a = "for i in range(10):\n    total = total + i * 2\n    print(i, total)\n".repeat(30);
+    let b = "for n in range(10):\n    print(n + 1)\n    total += n\n".repeat(30);
+    println!("{} and {} bytes, {} rounds each", a.len(), b.len(), ROUNDS);
+    for description in similarity::ALGORITHMS {
+        let scorer = description.algorithm.scorer();
+        let (a, b) = (scorer.prepare(&a), scorer.prepare(&b));
+        // the first one fills the scorer's buffers, which doesn't happen per pair
+        scorer.score(&a, &b);
+        let before = counting::allocations();
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            black_box(scorer.score(black_box(&a), black_box(&b)));
+        }
+        let elapsed = start.elapsed() / ROUNDS as u32;
+        let allocations = (counting::allocations() - before) as f64 / ROUNDS as f64;
+        println!("{:16} {:>12?} {:>8.1} allocations per pair", description.name, elapsed, allocations);
+    }
+}
@@ -0,0 +1,139 @@
+//! Compares one new file against an existing set, remembering scores between runs.
+//!
+//! The store is one line per pair, `score<TAB>hash<TAB>hash<TAB>file<TAB>file`,
+//! where the hashes are of the files' normalized text. Pairs are looked up by
+//! the hashes, so a file that changed gets compared again, and adding the same
+//! file twice doesn't compare anything twice.
+use crate::similarity::{count_rarity, PreparedFile};
+use cheat_checker::compare::{compare_all, Comparing};
+use crate::{load_file, CliArgs, Loading};
+use anyhow::Context;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The same pair of hashes whichever way around they're given
+fn key(a: u64, b: u64) -> (u64, u64) {
+    (a.min(b), a.max(b))
+}
+
+/// Reads the scores already in the store, which might not exist yet
+fn read_store(path: &Path) -> anyhow::Result<HashMap<(u64, u64), f64>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut scores = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut fields = line.split('\t');
+        let parsed = (|| {
+            let score: f64 = fields.next()?.parse().ok()?;
+            let a = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let b = u64::from_str_radix(fields.next()?, 16).ok()?;
+            Some((key(a, b), score))
+        })();
+        match parsed {
+            Some((pair, score)) => {
+                scores.insert(pair, score);
+            }
+            None => log::warn!("Skipping line {} of {}, it's not a score.", number + 1, path.to_string_lossy()),
+        }
+    }
+    Ok(scores)
+}
+
+/// Compares `new` against `against`, using and adding to the scores in `store`,
+/// and prints the pairs scoring at least `sensitivity`
+pub fn add(
+    new: &Path,
+    against: &[PathBuf],
+    store: Option<&Path>,
+    opts: &CliArgs,
+    loading: &Loading,
+    sensitivity: f64,
+) -> anyhow::Result<()> {
+    let new = std::fs::canonicalize(new).with_context(|| format!("couldn't find {}", new.to_string_lossy()))?;
+    let mut files: HashMap<PathBuf, String> = HashMap::new();
+    for path in against.iter().chain([&new]) {
+        match load_file(path, opts, loading) {
+            Ok(Some(contents)) => {
+                files.insert(path.clone(), contents);
+            }
+            Ok(None) => (),
+            Err(err) if *path == new => return Err(err),
+            Err(err) => log::warn!("Couldn't load {}, skipping it. ({:#})", path.to_string_lossy(), err),
+        }
+    }
+    let hashes: HashMap<&PathBuf, u64> = files.iter().map(|(path, text)| (path, text_hash(text))).collect();
+    let known = match store {
+        Some(store) => read_store(store)?,
+        None => HashMap::new(),
+    };
+
+    let new_hash = hashes[&new];
+    let mut scores: Vec<(&PathBuf, f64)> = Vec::new();
+    let mut workqueue: Vec<(&PathBuf, &PathBuf)> = Vec::new();
+    for other in files.keys().filter(|other| **other != new) {
+        match known.get(&key(new_hash, hashes[other])) {
+            Some(&score) => scores.push((other, score)),
+            None => workqueue.push((&new, other)),
+        }
+    }
+    log::info!(
+        "Comparing {} against {} files, {} were already scored.",
+        new.to_string_lossy(),
+        scores.len() + workqueue.len(),
+        scores.len()
+    );
+
+    let scorer = opts.algorithm.scorer();
+    let prepared: HashMap<PathBuf, PreparedFile> =
+        files.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+    let stop = AtomicBool::new(false);
+    let mut fresh: Vec<(&PathBuf, f64)> = Vec::new();
+    let comparing = Comparing::new(opts.algorithm, count_rarity(opts.algorithm, prepared.values()));
+    compare_all(workqueue, &prepared, &comparing, opts.jobs, &stop, |_, other, score| {
+        fresh.push((other, score));
+    });
+    if let Some(store) = store {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(store)
+            .with_context(|| format!("couldn't open {}", store.to_string_lossy()))?;
+        // one write, so two runs at once can't mix up their lines
+        let mut lines = String::new();
+        for (other, score) in &fresh {
+            lines.push_str(&format!(
+                "{:.6}\t{:016x}\t{:016x}\t{}\t{}\n",
+                score,
+                new_hash,
+                hashes[other],
+                new.to_string_lossy(),
+                other.to_string_lossy()
+            ));
+        }
+        file.write_all(lines.as_bytes())?;
+    }
+    scores.extend(fresh);
+
+    let metric = opts.metric;
+    let max_similarity = opts.max_sensitivity.map_or(f64::INFINITY, |max| metric.convert(max));
+    scores.retain(|&(_, score)| score >= sensitivity && score <= max_similarity);
+    scores.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    for (other, score) in scores {
+        println!("{:.6}\t{}\t{}", metric.convert(score), new.to_string_lossy(), other.to_string_lossy());
+    }
+    Ok(())
+}
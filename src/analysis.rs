@@ -0,0 +1,615 @@
+//! Looks at the whole set of files at once, instead of pair by pair.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Lines shorter than this (like `}` or `else:`) are in everything, and aren't interesting
+const MIN_LINE_LENGTH: usize = 8;
+
+/// Finds lines that appear in more than `min_files` files.
+///
+/// Lines are compared with their whitespace collapsed. The most widespread
+/// lines come first, each with the sorted list of files containing it.
+pub fn common_lines(files: &HashMap<PathBuf, String>, min_files: usize) -> Vec<(String, Vec<&PathBuf>)> {
+    let mut found: HashMap<String, BTreeSet<&PathBuf>> = HashMap::new();
+    for (path, contents) in files {
+        for line in contents.lines() {
+            let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            if line.len() < MIN_LINE_LENGTH {
+                continue;
+            }
+            found.entry(line).or_default().insert(path);
+        }
+    }
+    let mut common: Vec<(String, Vec<&PathBuf>)> = found
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > min_files)
+        .map(|(line, paths)| (line, paths.into_iter().collect()))
+        .collect();
+    common.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    common
+}
+
+/// A file and how similar it is to some other file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Neighbor<'a> {
+    score: f64,
+    path: &'a PathBuf,
+}
+
+impl Eq for Neighbor<'_> {}
+
+impl Ord for Neighbor<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.score.total_cmp(&other.score).then_with(|| self.path.cmp(other.path))
+    }
+}
+
+impl PartialOrd for Neighbor<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Keeps track of the K most similar files to each file, as scores come in
+pub struct Nearest<'a> {
+    k: usize,
+    // min-heaps, so the least similar neighbor is the one that gets kicked out
+    heaps: HashMap<&'a PathBuf, BinaryHeap<Reverse<Neighbor<'a>>>>,
+}
+
+impl<'a> Nearest<'a> {
+    pub fn new(k: usize) -> Self {
+        Nearest { k, heaps: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, x: &'a PathBuf, y: &'a PathBuf, score: f64) {
+        for (file, other) in [(x, y), (y, x)] {
+            let heap = self.heaps.entry(file).or_default();
+            heap.push(Reverse(Neighbor { score, path: other }));
+            if heap.len() > self.k {
+                heap.pop();
+            }
+        }
+    }
+
+    /// Every file, in order, with its neighbors from most to least similar
+    pub fn into_sorted(self) -> Vec<(&'a PathBuf, Vec<(&'a PathBuf, f64)>)> {
+        let mut files: Vec<_> = self
+            .heaps
+            .into_iter()
+            .map(|(file, heap)| {
+                // ascending order of Reverse is descending order of score
+                let neighbors = heap
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse(neighbor)| (neighbor.path, neighbor.score))
+                    .collect();
+                (file, neighbors)
+            })
+            .collect();
+        files.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        files
+    }
+}
+
+/// A pair and its score, ordered by score
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ranked<'a> {
+    score: f64,
+    x: &'a PathBuf,
+    y: &'a PathBuf,
+}
+
+impl Eq for Ranked<'_> {}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.score.total_cmp(&other.score).then_with(|| (self.x, self.y).cmp(&(other.x, other.y)))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Most pairs `TopScores` keeps, and most ties on top of those, so a low
+/// --percentile over a huge run still fits in memory
+pub const MAX_TOP_SCORES: usize = 1_000_000;
+
+/// Keeps the `k` most similar pairs as scores come in, plus any tied with the least similar of them.
+///
+/// `k` is capped at `MAX_TOP_SCORES`, and so are the ties. Past either cap
+/// it's not exact anymore, and `is_exact` says so.
+pub struct TopScores<'a> {
+    k: usize,
+    // a min-heap, so the least similar pair is the one that gets kicked out
+    heap: BinaryHeap<Reverse<Ranked<'a>>>,
+    /// Pairs that got kicked out, but have the same score as the least similar one left
+    ties: Vec<Ranked<'a>>,
+    /// Whether it was asked for more than it keeps, or a tie got dropped
+    capped: bool,
+}
+
+impl<'a> TopScores<'a> {
+    pub fn new(k: usize) -> Self {
+        TopScores {
+            k: k.clamp(1, MAX_TOP_SCORES),
+            heap: BinaryHeap::new(),
+            ties: Vec::new(),
+            capped: k > MAX_TOP_SCORES,
+        }
+    }
+
+    /// Whether every pair that should've been kept was
+    pub fn is_exact(&self) -> bool {
+        !self.capped
+    }
+
+    pub fn insert(&mut self, x: &'a PathBuf, y: &'a PathBuf, score: f64) {
+        self.heap.push(Reverse(Ranked { score, x, y }));
+        if self.heap.len() <= self.k {
+            return;
+        }
+        let Some(Reverse(out)) = self.heap.pop() else {
+            return;
+        };
+        let least = self.heap.peek().map_or(f64::NEG_INFINITY, |Reverse(least)| least.score);
+        if self.ties.first().is_some_and(|tie| tie.score < least) {
+            self.ties.clear();
+        }
+        if out.score == least {
+            if self.ties.len() < MAX_TOP_SCORES {
+                self.ties.push(out);
+            } else {
+                self.capped = true;
+            }
+        }
+    }
+
+    /// Every pair kept, most similar first
+    pub fn into_sorted(self) -> Vec<(&'a PathBuf, &'a PathBuf, f64)> {
+        let mut kept: Vec<Ranked> = self.heap.into_iter().map(|Reverse(ranked)| ranked).chain(self.ties).collect();
+        kept.sort_unstable_by(|a, b| b.cmp(a));
+        kept.into_iter().map(|ranked| (ranked.x, ranked.y, ranked.score)).collect()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn top_scores_keep_ties() {
+    let paths: Vec<PathBuf> = (0..6).map(|i| PathBuf::from(i.to_string())).collect();
+    let mut top = TopScores::new(2);
+    for (path, score) in paths.iter().zip([0.1, 0.5, 0.5, 0.9, 0.5, 0.2]) {
+        top.insert(path, path, score);
+    }
+    let kept: Vec<f64> = top.into_sorted().into_iter().map(|(_, _, score)| score).collect();
+    assert_eq!(kept, [0.9, 0.5, 0.5, 0.5]);
+    assert!(TopScores::new(MAX_TOP_SCORES).is_exact());
+    assert!(!TopScores::new(MAX_TOP_SCORES + 1).is_exact());
+}
+
+/// Estimates one quantile of a stream of numbers without keeping them, with the
+/// P² algorithm (Jain and Chlamtac, 1985). Five markers follow the minimum, the
+/// quantile, the maximum and the quantiles halfway to each, moving along a
+/// parabola fitted through their neighbours as numbers come in.
+pub struct Quantile {
+    /// Between 0 and 1
+    p: f64,
+    /// How many numbers have come in
+    count: usize,
+    /// Each marker's estimate, and the first five numbers until there are five
+    heights: [f64; 5],
+    /// How many numbers are at or below each marker
+    positions: [f64; 5],
+    /// Where they should be
+    desired: [f64; 5],
+}
+
+impl Quantile {
+    pub fn new(p: f64) -> Self {
+        Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [0.0, 1.0, 2.0, 3.0, 4.0],
+            desired: [0.0, 2.0 * p, 4.0 * p, 2.0 + 2.0 * p, 4.0],
+        }
+    }
+
+    pub fn insert(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+        let h = &mut self.heights;
+        // the cell it lands in, stretching the ends if it's past them
+        let cell = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[4] {
+            h[4] = x;
+            3
+        } else {
+            (0..4).rfind(|&i| h[i] <= x).unwrap_or(0)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        let p = self.p;
+        for (desired, step) in self.desired.iter_mut().zip([0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0]) {
+            *desired += step;
+        }
+        let n = &mut self.positions;
+        for i in 1..4 {
+            let off = self.desired[i] - n[i];
+            if (off >= 1.0 && n[i + 1] - n[i] > 1.0) || (off <= -1.0 && n[i - 1] - n[i] < -1.0) {
+                let s = off.signum();
+                let parabolic = h[i]
+                    + s / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + s) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - s) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                h[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    // the parabola overshot a neighbour, so just go in a straight line to it
+                    let j = if s > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + s * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                n[i] += s;
+            }
+        }
+    }
+
+    /// The estimate so far, nothing if there haven't been any numbers
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            // still just the numbers themselves, so it's exact, by the nearest rank
+            1..=4 => {
+                let mut seen = self.heights[..self.count].to_vec();
+                seen.sort_unstable_by(f64::total_cmp);
+                let rank = (self.p * self.count as f64).ceil() as usize;
+                Some(seen[rank.clamp(1, self.count) - 1])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn quantiles_are_estimated() {
+    // a shuffled 0..10000, by stepping through it coprimely
+    let numbers = (0..10_000).map(|i| (i * 7_919 % 10_000) as f64 / 10_000.0);
+    for p in [0.01, 0.5, 0.9, 0.99] {
+        let mut quantile = Quantile::new(p);
+        numbers.clone().for_each(|x| quantile.insert(x));
+        let estimate = quantile.estimate().unwrap();
+        assert!((estimate - p).abs() < 0.01, "{} came out as {}", p, estimate);
+    }
+    let mut few = Quantile::new(0.5);
+    assert_eq!(few.estimate(), None);
+    [3.0, 1.0, 2.0].into_iter().for_each(|x| few.insert(x));
+    assert_eq!(few.estimate(), Some(2.0));
+}
+
+/// How unpredictable a file's characters are, in bits per character.
+///
+/// Repetitive filler and generated files score low, most code is around 4 to 5.
+pub fn entropy(text: &str) -> f64 {
+    // in order, so adding up floats gives the same answer every time
+    let mut counts: BTreeMap<char, usize> = BTreeMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let total = counts.values().sum::<usize>() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Groups files that are exactly the same after normalizing, biggest group first
+pub fn duplicate_groups(files: &HashMap<PathBuf, String>) -> Vec<Vec<&PathBuf>> {
+    let mut by_text: HashMap<&str, Vec<&PathBuf>> = HashMap::new();
+    for (path, text) in files {
+        by_text.entry(text).or_default().push(path);
+    }
+    let mut groups: Vec<Vec<&PathBuf>> = by_text.into_values().filter(|group| group.len() > 1).collect();
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    groups
+}
+
+/// Scores each file against every other group's lines pooled together, and
+/// keeps the group it matches best, most similar first.
+///
+/// A file's score is how much of its lines are in the pool, so copying from
+/// a group scores high however much else the group wrote.
+pub fn centroid_matches<'a, G: AsRef<Path>>(files: &'a [(&'a PathBuf, G, Vec<u64>)]) -> Vec<(&'a PathBuf, &'a Path, f64)> {
+    let mut pools: HashMap<&Path, HashSet<u64>> = HashMap::new();
+    for (_, group, lines) in files {
+        pools.entry(group.as_ref()).or_default().extend(lines);
+    }
+    let mut matches: Vec<(&PathBuf, &Path, f64)> = files
+        .iter()
+        .filter_map(|(path, group, lines)| {
+            let (path, group) = (*path, group.as_ref());
+            pools
+                .iter()
+                .filter(|(other, _)| **other != group)
+                .map(|(&other, pool)| {
+                    let shared = lines.iter().filter(|line| pool.contains(line)).count();
+                    (path, other, shared as f64 / lines.len().max(1) as f64)
+                })
+                .max_by(|a, b| a.2.partial_cmp(&b.2).expect("Couldn't compare two scores").then_with(|| b.1.cmp(a.1)))
+        })
+        .collect();
+    matches.sort_unstable_by(|a, b| b.2.partial_cmp(&a.2).expect("Couldn't compare two scores").then_with(|| a.0.cmp(b.0)));
+    matches
+}
+
+/// Keeps the most similar pairs, leaving out any that would show a file
+/// more than `max` times. The rest stay in the order they were given.
+pub fn cap_per_file<'a>(pairs: &[(&'a PathBuf, &'a PathBuf, f64)], max: usize) -> Vec<(&'a PathBuf, &'a PathBuf, f64)> {
+    let mut by_score: Vec<usize> = (0..pairs.len()).collect();
+    by_score.sort_by(|&a, &b| pairs[b].2.partial_cmp(&pairs[a].2).expect("Couldn't compare two scores"));
+    let mut counts: HashMap<&PathBuf, usize> = HashMap::new();
+    let mut kept = vec![false; pairs.len()];
+    for i in by_score {
+        let (x, y, _) = pairs[i];
+        if counts.get(x).copied().unwrap_or(0) < max && counts.get(y).copied().unwrap_or(0) < max {
+            *counts.entry(x).or_default() += 1;
+            *counts.entry(y).or_default() += 1;
+            kept[i] = true;
+        }
+    }
+    pairs.iter().zip(kept).filter(|(_, kept)| *kept).map(|(&pair, _)| pair).collect()
+}
+
+/// Scores so low they're just noise, it doesn't mean anything when they tie
+const TRIVIAL_SCORE: f64 = 0.1;
+
+/// Finds scores shared by suspiciously many pairs, which usually means the
+/// files all contain the same template, and returns each with how many pairs have it.
+///
+/// Scores are compared to 6 decimal places, same as they're shown.
+pub fn score_ties(scores: impl Iterator<Item = f64>) -> Vec<(f64, usize)> {
+    let mut total = 0;
+    let mut counts: HashMap<i64, usize> = HashMap::new();
+    for score in scores {
+        total += 1;
+        if score > TRIVIAL_SCORE {
+            *counts.entry((score * 1e6).round() as i64).or_default() += 1;
+        }
+    }
+    // a few ties happen by chance, so only care when it's a lot of pairs
+    let minimum = (total / 20).max(5);
+    let mut ties: Vec<(f64, usize)> = counts
+        .into_iter()
+        .filter(|&(_, count)| count >= minimum)
+        .map(|(score, count)| (score as f64 / 1e6, count))
+        .collect();
+    ties.sort_unstable_by_key(|&(_, count)| Reverse(count));
+    ties
+}
+
+/// Finds the longest run of text that's in both `a` and `b`.
+///
+/// This is quadratic like Levenshtein, so it's only worth doing for pairs that got flagged.
+pub fn longest_common_substring(a: &str, b: &str) -> String {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // length of the common run ending at a[i] and b[j], one row at a time
+    let mut previous = vec![0usize; b.len() + 1];
+    let mut current = vec![0usize; b.len() + 1];
+    let (mut best_end, mut best_len) = (0, 0);
+    for (i, ca) in a.iter().enumerate() {
+        for (j, cb) in b.iter().enumerate() {
+            current[j + 1] = if ca == cb { previous[j] + 1 } else { 0 };
+            if current[j + 1] > best_len {
+                best_len = current[j + 1];
+                best_end = i + 1;
+            }
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    a[best_end - best_len..best_end].iter().collect()
+}
+
+/// A line-by-line diff of `a` against `b`, as each line with whether it's
+/// only in `a` (`-`), only in `b` (`+`) or in both (` `).
+///
+/// Also quadratic, it's for looking at one pair closely.
+pub fn line_diff<'a>(a: &'a str, b: &'a str) -> Vec<(char, &'a str)> {
+    let (a, b): (Vec<&str>, Vec<&str>) = (a.lines().collect(), b.lines().collect());
+    // longest common subsequence of what's left of both, from the end
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            diff.push((' ', a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            diff.push(('-', a[i]));
+            i += 1;
+        } else {
+            diff.push(('+', b[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+/// Finds each group's closest match in any other group, most similar first.
+///
+/// Pairs where either file isn't in a group, or both are in the same one, don't count.
+pub fn group_summary<'a>(
+    scores: impl Iterator<Item = (&'a PathBuf, &'a PathBuf, f64)>,
+    group: impl Fn(&'a PathBuf) -> Option<&'a Path>,
+) -> Vec<(&'a Path, &'a Path, f64)> {
+    let mut best: HashMap<&Path, (&Path, f64)> = HashMap::new();
+    for (x, y, score) in scores {
+        let (Some(a), Some(b)) = (group(x), group(y)) else {
+            continue;
+        };
+        if a == b {
+            continue;
+        }
+        for (group, other) in [(a, b), (b, a)] {
+            let entry = best.entry(group).or_insert((other, score));
+            if score > entry.1 {
+                *entry = (other, score);
+            }
+        }
+    }
+    let mut summary: Vec<_> = best.into_iter().map(|(group, (other, score))| (group, other, score)).collect();
+    summary.sort_unstable_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+    summary
+}
+
+/// A pair that was flagged in at least one of two runs, with its score in each
+pub struct Change<'a> {
+    pub a: &'a Path,
+    pub b: &'a Path,
+    pub old: Option<f64>,
+    pub new: Option<f64>,
+}
+
+/// Lines up the pairs of two runs, keeping the ones either run flagged,
+/// biggest change first. Pairs need to be keyed the same way in both.
+pub fn delta<'a>(
+    old: &'a HashMap<(PathBuf, PathBuf), f64>,
+    new: &'a HashMap<(PathBuf, PathBuf), f64>,
+    flagged: impl Fn(f64) -> bool,
+) -> Vec<Change<'a>> {
+    let mut changes: Vec<Change> = old
+        .keys()
+        .chain(new.keys().filter(|pair| !old.contains_key(*pair)))
+        .map(|(a, b)| Change {
+            a,
+            b,
+            old: old.get(&(a.clone(), b.clone())).copied(),
+            new: new.get(&(a.clone(), b.clone())).copied(),
+        })
+        .filter(|change| change.old.is_some_and(&flagged) || change.new.is_some_and(&flagged))
+        .collect();
+    let size = |change: &Change| (change.new.unwrap_or(0.0) - change.old.unwrap_or(0.0)).abs();
+    changes.sort_unstable_by(|x, y| {
+        size(y)
+            .partial_cmp(&size(x))
+            .expect("Couldn't compare two scores")
+            .then_with(|| (x.a, x.b).cmp(&(y.a, y.b)))
+    });
+    changes
+}
+
+/// How the scores a pair got in different runs are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    Max,
+    Mean,
+}
+
+impl FromStr for Combine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "max" => Ok(Combine::Max),
+            "mean" => Ok(Combine::Mean),
+            _ => Err(format!("unknown way to combine scores \"{}\", expected max or mean", s)),
+        }
+    }
+}
+
+/// Combines the similarities of several runs into one score for each pair
+/// that's in any of them. Pairs need to be keyed the same way in every run.
+pub fn combine_runs(runs: &[HashMap<(PathBuf, PathBuf), f64>], combine: Combine) -> HashMap<(PathBuf, PathBuf), f64> {
+    let mut seen: HashMap<&(PathBuf, PathBuf), Vec<f64>> = HashMap::new();
+    for run in runs {
+        for (pair, &score) in run {
+            seen.entry(pair).or_default().push(score);
+        }
+    }
+    seen.into_iter()
+        .map(|(pair, scores)| {
+            let score = match combine {
+                Combine::Max => scores.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                Combine::Mean => scores.iter().sum::<f64>() / scores.len() as f64,
+            };
+            (pair.clone(), score)
+        })
+        .collect()
+}
+
+/// Runs of matching lines shorter than this are usually just boilerplate
+const MIN_RANGE_LINES: usize = 3;
+
+/// Finds up to `count` of the longest runs of lines that are in both `a` and
+/// `b`, longest first, as line numbers counting from 1.
+///
+/// Lines match when they're the same apart from whitespace, and blank lines
+/// are skipped over. The runs don't overlap each other.
+pub fn matching_ranges(a: &str, b: &str, count: usize) -> Vec<(RangeInclusive<usize>, RangeInclusive<usize>)> {
+    let lines = |text: &str| -> Vec<(usize, String)> {
+        text.lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.split_whitespace().collect::<Vec<_>>().join(" ")))
+            .filter(|(_, line)| !line.is_empty())
+            .collect()
+    };
+    let (a, b) = (lines(a), lines(b));
+    let (mut used_a, mut used_b) = (vec![false; a.len()], vec![false; b.len()]);
+    let mut ranges = Vec::new();
+    while ranges.len() < count {
+        // same as longest_common_substring, but lines already in a range break runs
+        let mut previous = vec![0usize; b.len() + 1];
+        let mut current = vec![0usize; b.len() + 1];
+        let (mut best_end, mut best_len) = ((0, 0), 0);
+        for (i, (_, la)) in a.iter().enumerate() {
+            for (j, (_, lb)) in b.iter().enumerate() {
+                current[j + 1] = if la == lb && !used_a[i] && !used_b[j] { previous[j] + 1 } else { 0 };
+                if current[j + 1] > best_len {
+                    best_len = current[j + 1];
+                    best_end = (i + 1, j + 1);
+                }
+            }
+            std::mem::swap(&mut previous, &mut current);
+        }
+        if best_len < MIN_RANGE_LINES {
+            break;
+        }
+        let (start_a, start_b) = (best_end.0 - best_len, best_end.1 - best_len);
+        used_a[start_a..best_end.0].fill(true);
+        used_b[start_b..best_end.1].fill(true);
+        ranges.push((a[start_a].0..=a[best_end.0 - 1].0, b[start_b].0..=b[best_end.1 - 1].0));
+    }
+    ranges
+}
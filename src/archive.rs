@@ -0,0 +1,89 @@
+//! Tar archives, read as if they were directories.
+//!
+//! A file inside an archive gets a path like `alice.tar.gz/src/main.py`,
+//! the archive's path with the entry's path after it. Nothing's extracted,
+//! its bytes come straight out of the archive when it's loaded.
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Whether a path is named like a tar archive, compressed or not
+pub fn is_tar(path: &Path) -> bool {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_ascii_lowercase()).unwrap_or_default();
+    [".tar", ".tar.gz", ".tgz", ".tar.bz2", ".tar.xz"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Reads and decompresses an archive, keeping it around so each one is only read once
+fn read(archive: &Path) -> anyhow::Result<Arc<Vec<u8>>> {
+    static READ: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<u8>>>>> = OnceLock::new();
+    let read = READ.get_or_init(Default::default);
+    if let Some(bytes) = read.lock().unwrap().get(archive) {
+        return Ok(bytes.clone());
+    }
+    let bytes = std::fs::read(archive)?;
+    let bytes = Arc::new(crate::decompress(archive, bytes)?);
+    read.lock().unwrap().insert(archive.to_path_buf(), bytes.clone());
+    Ok(bytes)
+}
+
+/// Where an entry would end up if it were extracted, or `None` for ones
+/// that would end up outside the archive, like `../../etc/passwd`.
+fn inner_path(entry: &Path) -> Option<PathBuf> {
+    let mut inner = PathBuf::new();
+    for component in entry.components() {
+        match component {
+            Component::Normal(part) => inner.push(part),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+    (!inner.as_os_str().is_empty()).then_some(inner)
+}
+
+/// Lists the regular files in an archive, as paths inside it
+pub fn files(archive: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let bytes = read(archive)?;
+    let mut files = Vec::new();
+    for entry in tar::Archive::new(&bytes[..]).entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?;
+        match inner_path(&path) {
+            Some(inner) => files.push(archive.join(inner)),
+            None => log::warn!(
+                "Skipping {} in {}, it points outside the archive.",
+                path.to_string_lossy(),
+                archive.to_string_lossy()
+            ),
+        }
+    }
+    Ok(files)
+}
+
+/// Splits a path inside an archive into the archive and the path inside it.
+/// Paths that aren't inside one are `None`.
+pub fn split(path: &Path) -> Option<(&Path, &Path)> {
+    if path.exists() {
+        return None;
+    }
+    let archive = path.ancestors().skip(1).find(|ancestor| is_tar(ancestor) && ancestor.is_file())?;
+    Some((archive, path.strip_prefix(archive).ok()?))
+}
+
+/// Reads one file out of an archive
+pub fn read_file(archive: &Path, inner: &Path) -> anyhow::Result<Vec<u8>> {
+    let bytes = read(archive)?;
+    for entry in tar::Archive::new(&bytes[..]).entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && inner_path(&entry.path()?).as_deref() == Some(inner) {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            return Ok(contents);
+        }
+    }
+    // the archive changed since it was listed
+    anyhow::bail!("{} isn't in {} anymore", inner.to_string_lossy(), archive.to_string_lossy())
+}
@@ -0,0 +1,44 @@
+//! Compares the structure of code instead of its text, by parsing it and
+//! dumping the syntax tree with every identifier replaced.
+use std::process::Command;
+use std::str::FromStr;
+
+/// Languages we know how to turn into a normalized AST
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstLanguage {
+    Python,
+}
+
+impl FromStr for AstLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "python" | "py" => Ok(AstLanguage::Python),
+            _ => Err(format!("no AST support for \"{}\", supported: python", s)),
+        }
+    }
+}
+
+/// Parses stdin with python's own `ast` module, anonymizes every name,
+/// and prints the tree without any line/column info.
+const PYTHON_DUMP: &str = r#"
+import ast, sys
+tree = ast.parse(sys.stdin.read())
+for node in ast.walk(tree):
+    for field in ("id", "arg", "name", "attr", "asname"):
+        if isinstance(getattr(node, field, None), str):
+            setattr(node, field, "_")
+print(ast.dump(tree, annotate_fields=False))
+"#;
+
+/// Serializes the normalized AST of some source code.
+///
+/// Fails if the parser isn't installed or the code doesn't parse.
+pub fn normalized_ast(language: AstLanguage, source: &str) -> anyhow::Result<String> {
+    match language {
+        AstLanguage::Python => {
+            crate::run_filter(Command::new("python3").arg("-c").arg(PYTHON_DUMP), source)
+        }
+    }
+}
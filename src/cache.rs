@@ -0,0 +1,83 @@
+//! Files loaded when they're needed instead of all up front, for --no-preload.
+//!
+//! Only the most recently used files are kept, so memory stays bounded
+//! however many files there are. The rest get read again when they're needed.
+use crate::{load_file, CliArgs, FormatterFailed, Loading};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The files that are loaded, and the order they were last used in, oldest first
+#[derive(Default)]
+struct Loaded {
+    files: HashMap<PathBuf, Arc<String>>,
+    order: VecDeque<PathBuf>,
+}
+
+pub struct FileCache<'a> {
+    opts: &'a CliArgs,
+    loading: &'a Loading,
+    capacity: usize,
+    loaded: Mutex<Loaded>,
+    /// Files that couldn't be loaded, so they only get warned about once
+    failed: Mutex<HashSet<PathBuf>>,
+}
+
+impl<'a> FileCache<'a> {
+    pub fn new(opts: &'a CliArgs, loading: &'a Loading, capacity: usize) -> Self {
+        FileCache {
+            opts,
+            loading,
+            capacity,
+            loaded: Mutex::new(Loaded::default()),
+            failed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Gets a file as it'll be compared, loading it if it isn't already.
+    /// Files that can't be loaded or get skipped are `None`.
+    pub fn get(&self, path: &Path) -> Option<Arc<String>> {
+        {
+            let Loaded { files, order } = &mut *self.loaded.lock().unwrap();
+            if let Some(text) = files.get(path) {
+                // the cache is small, so finding it in here is cheap
+                if let Some(i) = order.iter().position(|used| used == path) {
+                    let used = order.remove(i).expect("position is in the queue");
+                    order.push_back(used);
+                }
+                return Some(text.clone());
+            }
+        }
+        if self.failed.lock().unwrap().contains(path) {
+            return None;
+        }
+        // loaded without holding the lock, so other threads can load files too
+        let text = match load_file(path, self.opts, self.loading) {
+            Ok(Some(text)) => Arc::new(text),
+            Ok(None) => {
+                self.failed.lock().unwrap().insert(path.to_path_buf());
+                return None;
+            }
+            Err(err) if err.is::<FormatterFailed>() => {
+                log::error!("Couldn't format {}. ({:#})", path.to_string_lossy(), err);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                log::warn!("Couldn't load {}, skipping its pairs. ({:#})", path.to_string_lossy(), err);
+                self.failed.lock().unwrap().insert(path.to_path_buf());
+                return None;
+            }
+        };
+        let Loaded { files, order } = &mut *self.loaded.lock().unwrap();
+        // another thread might have loaded it meanwhile
+        if files.insert(path.to_path_buf(), text.clone()).is_none() {
+            order.push_back(path.to_path_buf());
+        }
+        while order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                files.remove(&oldest);
+            }
+        }
+        Some(text)
+    }
+}
@@ -0,0 +1,110 @@
+//! Groups near-duplicate files with MinHash and locality-sensitive hashing,
+//! without scoring every pair.
+//!
+//! Each file's line fingerprints are boiled down to a short signature, where
+//! two signatures agree in about as many places as the files' Jaccard
+//! similarity. Files whose signatures agree on a whole band of rows land in
+//! the same bucket, and end up in the same cluster.
+use crate::similarity::{Algorithm, Overlap};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Rows per band, more makes buckets pickier
+const ROWS: usize = 4;
+/// Bands, more finds more of the less similar pairs. With 4 rows of 16 bands,
+/// pairs around 0.5 similar have even odds of sharing a bucket.
+const BANDS: usize = 16;
+const SIGNATURE_LENGTH: usize = ROWS * BANDS;
+
+/// A quick, well-mixed hash, seeded to get a different function for each row
+fn mix(value: u64, seed: u64) -> u64 {
+    // splitmix64
+    let mut z = value ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn signature(fingerprints: &[u64]) -> [u64; SIGNATURE_LENGTH] {
+    let mut signature = [u64::MAX; SIGNATURE_LENGTH];
+    for &fingerprint in fingerprints {
+        for (seed, lowest) in signature.iter_mut().enumerate() {
+            *lowest = (*lowest).min(mix(fingerprint, seed as u64));
+        }
+    }
+    signature
+}
+
+/// How many places two signatures agree in, roughly their files' similarity
+fn estimate(a: &[u64], b: &[u64]) -> f64 {
+    a.iter().zip(b).filter(|(a, b)| a == b).count() as f64 / a.len() as f64
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    // point everything straight at the root, so later finds are quick
+    let mut i = i;
+    while parents[i] != root {
+        let next = parents[i];
+        parents[i] = root;
+        i = next;
+    }
+    root
+}
+
+/// Clusters of at least two files, biggest first, each with the average
+/// estimated similarity between its files
+pub fn clusters(files: &HashMap<PathBuf, String>) -> Vec<(Vec<&PathBuf>, f64)> {
+    let lines = Algorithm::Lines(Overlap::Jaccard).scorer();
+    let mut paths: Vec<&PathBuf> = files.keys().collect();
+    paths.sort();
+    let signatures: Vec<[u64; SIGNATURE_LENGTH]> = paths
+        .iter()
+        .map(|path| signature(&lines.prepare(&files[*path]).fingerprints))
+        .collect();
+
+    let mut parents: Vec<usize> = (0..paths.len()).collect();
+    for band in 0..BANDS {
+        let mut buckets: HashMap<&[u64], usize> = HashMap::new();
+        for (i, signature) in signatures.iter().enumerate() {
+            let rows = &signature[band * ROWS..(band + 1) * ROWS];
+            // empty files all have the same signature, but they aren't copies of anything
+            if rows.iter().all(|&row| row == u64::MAX) {
+                continue;
+            }
+            if let Some(&first) = buckets.get(rows) {
+                let (a, b) = (find(&mut parents, first), find(&mut parents, i));
+                parents[a] = b;
+            } else {
+                buckets.insert(rows, i);
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..paths.len() {
+        let root = find(&mut parents, i);
+        members.entry(root).or_default().push(i);
+    }
+    let mut clusters: Vec<(Vec<&PathBuf>, f64)> = members
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let mut total = 0.0;
+            let mut pairs = 0;
+            for (n, &a) in members.iter().enumerate() {
+                for &b in &members[n + 1..] {
+                    total += estimate(&signatures[a], &signatures[b]);
+                    pairs += 1;
+                }
+            }
+            let paths = members.iter().map(|&i| paths[i]).collect();
+            (paths, total / pairs as f64)
+        })
+        .collect();
+    clusters.sort_unstable_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+    clusters
+}
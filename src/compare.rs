@@ -0,0 +1,284 @@
+//! Spreading comparisons over worker threads, for everything that compares a whole set of files.
+//!
+//! The Python module uses this too, so nothing in here knows about the command line.
+use crate::similarity;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Makes every comparison in the workqueue, spread over `jobs` threads.
+///
+/// `on_result` is called on this thread as each comparison finishes, so it
+/// doesn't need to be thread-safe. Anything written for a result (output,
+/// the logfile) has to happen in there, never in the workers, so lines from
+/// different threads can't get mixed together.
+///
+/// Pairs that can't possibly score `comparing.skip_below` get that bound as
+/// their score instead of being compared, see `WorkerState::compare`.
+pub fn compare_all<'a>(
+    workqueue: Vec<(&'a PathBuf, &'a PathBuf)>,
+    files: &HashMap<PathBuf, similarity::PreparedFile>,
+    comparing: &Comparing,
+    jobs: usize,
+    stop: &AtomicBool,
+    on_result: impl FnMut(&'a PathBuf, &'a PathBuf, f64),
+) {
+    let compare = |state: &mut WorkerState, x: &PathBuf, y: &PathBuf| Some(state.compare(&files[x], &files[y]));
+    compare_with(workqueue, compare, comparing, jobs, stop, on_result);
+}
+
+/// Runs `compare` on every pair in the workqueue, the rest of `compare_all`
+pub fn compare_with<'a>(
+    workqueue: Vec<(&'a PathBuf, &'a PathBuf)>,
+    compare: impl Fn(&mut WorkerState, &PathBuf, &PathBuf) -> Option<f64> + Sync,
+    comparing: &Comparing,
+    jobs: usize,
+    stop: &AtomicBool,
+    mut on_result: impl FnMut(&'a PathBuf, &'a PathBuf, f64),
+) {
+    // not worth starting threads for something like comparing just two files
+    if let [(x, y)] = workqueue[..] {
+        if !stop.load(Ordering::Relaxed) {
+            if let Some(score) = compare_caught(&compare, &mut WorkerState::new(comparing), comparing, x, y) {
+                on_result(x, y, score);
+            }
+        }
+        return;
+    }
+    // no point in having more threads than there are comparisons to make
+    let jobs = jobs.min(workqueue.len()).max(1);
+    log::debug!("Starting {} worker threads for {} comparisons.", jobs, workqueue.len());
+    let workqueue = Arc::new(Mutex::new(workqueue));
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        // worker threads
+        for x in 0..jobs {
+            let workqueue = workqueue.clone();
+            let tx = tx.clone();
+            // give the thread a name in case we have to debug specific threads later
+            thread::Builder::new()
+                .name(x.to_string())
+                .spawn_scoped(scope, || work(workqueue, &compare, comparing, tx, stop))
+                .unwrap();
+        }
+        // only the workers have senders now, so this ends once they've all exited
+        drop(tx);
+        // loop runs once per message from the worker threads (blocking while waiting)
+        for (x, y, score) in rx.iter() {
+            on_result(x, y, score);
+        }
+    });
+}
+
+/// How every worker compares pairs, whatever the files are
+#[derive(Clone)]
+pub struct Comparing {
+    pub algorithm: similarity::Algorithm,
+    /// Pairs whose upper bound is under this aren't worth scoring
+    pub skip_below: Option<f64>,
+    /// How common each shingle is, for tf-idf
+    pub rarity: Option<Arc<similarity::Rarity>>,
+    /// Shared by every worker, for --max-cps
+    pub throttle: Option<Arc<Throttle>>,
+}
+
+impl Comparing {
+    /// Every pair gets scored, as fast as it can be
+    pub fn new(algorithm: similarity::Algorithm, rarity: Option<Arc<similarity::Rarity>>) -> Self {
+        Comparing { algorithm, skip_below: None, rarity, throttle: None }
+    }
+}
+
+/// Everything a worker thread keeps around between comparisons
+pub struct WorkerState {
+    /// Has its own buffers, so it's only ever used by this thread
+    pub scorer: Box<dyn similarity::Similarity>,
+    /// Pairs whose upper bound is under this aren't worth scoring
+    skip_below: Option<f64>,
+    throttle: Option<Arc<Throttle>>,
+}
+
+impl WorkerState {
+    pub fn new(comparing: &Comparing) -> Self {
+        WorkerState {
+            scorer: comparing.algorithm.scorer_with(comparing.rarity.clone()),
+            skip_below: comparing.skip_below,
+            throttle: comparing.throttle.clone(),
+        }
+    }
+
+    pub fn compare(&mut self, a: &similarity::PreparedFile, b: &similarity::PreparedFile) -> f64 {
+        // variants and parts can score higher than the files themselves, so there's no bound for those
+        let plain = |file: &similarity::PreparedFile| file.variants.is_empty() && file.parts.is_empty();
+        if let Some(floor) = self.skip_below.filter(|_| plain(a) && plain(b)) {
+            let bound = self.scorer.upper_bound(a, b);
+            // strictly below, a pair that could only just make it still gets scored
+            if bound < floor {
+                return bound;
+            }
+        }
+        similarity::best_granular(&*self.scorer, a, b).0
+    }
+}
+
+/// Makes one comparison, leaving the pair out if it panics instead of taking
+/// the whole run down with it
+fn compare_caught(
+    compare: &impl Fn(&mut WorkerState, &PathBuf, &PathBuf) -> Option<f64>,
+    state: &mut WorkerState,
+    comparing: &Comparing,
+    x: &PathBuf,
+    y: &PathBuf,
+) -> Option<f64> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| compare(state, x, y))) {
+        Ok(score) => score,
+        Err(_) => {
+            log::error!(
+                "Comparing {} and {} crashed, leaving the pair out. This is a bug, please report it.",
+                x.to_string_lossy(),
+                y.to_string_lossy()
+            );
+            // it might've been halfway through using its buffers
+            *state = WorkerState::new(comparing);
+            None
+        }
+    }
+}
+
+/// Spaces out comparisons over every worker thread, for --max-cps
+pub struct Throttle {
+    interval: Duration,
+    /// When the next comparison is allowed to start
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(per_second: f64) -> Self {
+        Throttle { interval: Duration::from_secs_f64(1.0 / per_second), next: Mutex::new(Instant::now()) }
+    }
+
+    /// Blocks until it's this thread's turn to make a comparison
+    pub fn wait(&self) {
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            // time spent idle doesn't build up into a burst later
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        thread::sleep(start.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Make comparisons until the workqueue is empty, or until told to stop
+fn work<'a>(
+    jobs: Arc<Mutex<Vec<(&'a PathBuf, &'a PathBuf)>>>,
+    compare: &impl Fn(&mut WorkerState, &PathBuf, &PathBuf) -> Option<f64>,
+    comparing: &Comparing,
+    results: Sender<(&'a PathBuf, &'a PathBuf, f64)>,
+    stop: &AtomicBool,
+) {
+    let mut state = WorkerState::new(comparing);
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        // lock() blocks the thread, the Result is just for if the mutex is poisoned.
+        // nothing panics while holding it, but the list's fine even if something did
+        let job = jobs
+            .lock()
+            .unwrap_or_else(|poisoned| {
+                log::error!("A worker thread crashed while taking a pair, carrying on without it.");
+                jobs.clear_poison();
+                poisoned.into_inner()
+            })
+            .pop();
+        match job {
+            None => break,
+            Some((x, y)) => {
+                if let Some(throttle) = &state.throttle {
+                    throttle.wait();
+                    // it might've been told to stop while waiting its turn
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                if let Some(score) = compare_caught(compare, &mut state, comparing, x, y) {
+                    let _ = results.send((x, y, score));
+                }
+            }
+        }
+    }
+    log::debug!(
+        "Worker thread {} exited.",
+        thread::current().name().unwrap()
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn panicking_comparisons_are_left_out() {
+    let paths: Vec<PathBuf> = ["a", "b", "c", "d"].iter().map(PathBuf::from).collect();
+    let workqueue: Vec<(&PathBuf, &PathBuf)> =
+        paths.iter().flat_map(|x| paths.iter().filter(move |y| x < *y).map(move |y| (x, y))).collect();
+    let compare = |_: &mut WorkerState, x: &PathBuf, _: &PathBuf| {
+        if x == std::path::Path::new("b") {
+            panic!("pathological input");
+        }
+        Some(0.5)
+    };
+    let mut scored = Vec::new();
+    let algorithm = similarity::Algorithm::Lines(similarity::Overlap::Jaccard);
+    compare_with(workqueue, compare, &Comparing::new(algorithm, None), 3, &AtomicBool::new(false), |x, y, _| {
+        scored.push((x, y))
+    });
+    scored.sort();
+    // every pair but the two starting with b
+    let names: Vec<_> = scored.iter().map(|(x, y)| format!("{}{}", x.display(), y.display())).collect();
+    assert_eq!(names, ["ab", "ac", "ad", "cd"]);
+}
+
+#[cfg(test)]
+#[test]
+fn skipping_pairs_keeps_the_ones_that_matter() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/determinism");
+    let texts: HashMap<PathBuf, String> = std::fs::read_dir(fixtures)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| (path.clone(), std::fs::read_to_string(path).unwrap()))
+        .collect();
+    let workqueue: Vec<(&PathBuf, &PathBuf)> =
+        texts.keys().flat_map(|x| texts.keys().filter(move |y| x < *y).map(move |y| (x, y))).collect();
+    let mut skipped = 0;
+    for description in similarity::ALGORITHMS {
+        let scorer = description.algorithm.scorer();
+        let files: HashMap<PathBuf, similarity::PreparedFile> =
+            texts.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+        let rarity = similarity::count_rarity(description.algorithm, files.values());
+        let comparing = Comparing::new(description.algorithm, rarity);
+        let scores = |comparing: &Comparing| {
+            let mut scores = HashMap::new();
+            compare_all(workqueue.clone(), &files, comparing, 2, &AtomicBool::new(false), |x, y, score| {
+                scores.insert((x, y), score);
+            });
+            scores
+        };
+        let all = scores(&comparing);
+        for floor in [0.2, 0.5, 0.8] {
+            let some = scores(&Comparing { skip_below: Some(floor), ..comparing.clone() });
+            for (pair, &score) in &all {
+                if score >= floor || some[pair] >= floor {
+                    assert_eq!(some[pair], score, "{} changed {:?} with a floor of {}", description.name, pair, floor);
+                } else if some[pair] != score {
+                    skipped += 1;
+                }
+            }
+        }
+    }
+    // or none of it was tested
+    assert!(skipped > 0);
+}
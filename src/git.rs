@@ -0,0 +1,22 @@
+//! Loads files as they were at some git revision, instead of from the working tree.
+use anyhow::Context;
+use std::path::Path;
+
+/// Reads the contents of a file at a revision (anything `git rev-parse` takes)
+/// of whatever repository it's in.
+pub fn read_at_revision(path: &Path, rev: &str) -> anyhow::Result<Vec<u8>> {
+    let repo = git2::Repository::discover(path)?;
+    let workdir = repo.workdir().context("repository is bare")?;
+    // paths are canonicalized already, but the workdir might not be
+    let workdir = std::fs::canonicalize(workdir)?;
+    let relative = path
+        .strip_prefix(&workdir)
+        .context("file isn't inside the repository's working tree")?;
+    let tree = repo.revparse_single(rev)?.peel_to_tree()?;
+    let blob = tree
+        .get_path(relative)
+        .with_context(|| format!("file doesn't exist at {}", rev))?
+        .to_object(&repo)?
+        .peel_to_blob()?;
+    Ok(blob.content().to_vec())
+}
@@ -0,0 +1,135 @@
+//! Every pair's score as one picture, for --heatmap.
+//!
+//! Files are put in an order that keeps similar ones next to each other, so
+//! a group of files that share code shows up as a bright block. Only the
+//! lower triangle is drawn, since the scores are the same both ways.
+use image::{Rgb, RgbImage};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Biggest a pair's square gets, when there's only a few files
+const MAX_CELL: u32 = 16;
+/// The picture's never wider than this, the squares shrink instead
+const MAX_SIZE: u32 = 4096;
+/// How far apart the labels are, so they don't run into each other
+const LABEL_SPACING: u32 = 6;
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+/// Pairs that weren't compared, like ones in the same group
+const MISSING: Rgb<u8> = Rgb([200, 200, 200]);
+const TEXT: Rgb<u8> = Rgb([0, 0, 0]);
+
+/// Tiny 3x5 digits for the labels, one row per byte, high bit on the left
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Puts the files in an order where each one is the closest match to the one before it.
+///
+/// Starts from a file in the most similar pair, so that pair's together.
+/// `paths` need to be sorted already, ties go to the first one so the
+/// order's the same every run.
+pub fn order<'a>(paths: &[&'a PathBuf], scores: &HashMap<(PathBuf, PathBuf), f64>) -> Vec<&'a PathBuf> {
+    let mut paths = paths.to_vec();
+    let score = |x: &PathBuf, y: &PathBuf| {
+        scores
+            .get(&(x.clone(), y.clone()))
+            .or_else(|| scores.get(&(y.clone(), x.clone())))
+            .copied()
+            .unwrap_or(0.0)
+    };
+    let included: HashSet<&PathBuf> = paths.iter().copied().collect();
+    let start = scores
+        .iter()
+        .filter(|((x, y), _)| included.contains(x) && included.contains(y))
+        .max_by(|a, b| a.1.total_cmp(b.1).then_with(|| b.0.cmp(a.0)))
+        .and_then(|((x, _), _)| paths.iter().position(|path| *path == x))
+        .unwrap_or(0);
+    let mut ordered = Vec::with_capacity(paths.len());
+    if paths.is_empty() {
+        return ordered;
+    }
+    ordered.push(paths.remove(start));
+    while !paths.is_empty() {
+        let last = ordered[ordered.len() - 1];
+        let mut next = 0;
+        for (i, path) in paths.iter().enumerate() {
+            if score(last, path) > score(last, paths[next]) {
+                next = i;
+            }
+        }
+        ordered.push(paths.remove(next));
+    }
+    ordered
+}
+
+/// Draws one label's digits with the top left corner at `(x, y)`, either across or downwards
+fn draw_label(image: &mut RgbImage, index: usize, x: u32, y: u32, downwards: bool) {
+    for (i, digit) in index.to_string().bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let (gx, gy) = if downwards { (x, y + i as u32 * LABEL_SPACING) } else { (x + i as u32 * 4, y) };
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    image.put_pixel(gx + column, gy + row as u32, TEXT);
+                }
+            }
+        }
+    }
+}
+
+/// Writes the heatmap of `n` files, with the pair at row `i` and column `j` colored by `score(i, j)`.
+///
+/// Each file's label is its index, so the picture needs a legend to go with it.
+pub fn write(
+    path: &Path,
+    n: usize,
+    score: impl Fn(usize, usize) -> Option<f64>,
+    color: impl Fn(f64) -> [u8; 3],
+) -> image::ImageResult<()> {
+    let cell = (MAX_SIZE / n.max(1) as u32).clamp(1, MAX_CELL);
+    // not every index gets a label when the squares are too small to fit one each
+    let step = LABEL_SPACING.div_ceil(cell) as usize;
+    let digits = n.saturating_sub(1).to_string().len() as u32;
+    let margin_left = digits * 4 + 2;
+    let margin_bottom = digits * LABEL_SPACING + 2;
+    let grid = cell * n as u32;
+    let mut image = RgbImage::from_pixel(margin_left + grid, grid + margin_bottom, BACKGROUND);
+    for i in 0..n {
+        for j in 0..i {
+            let pixel = score(i, j).map_or(MISSING, |score| Rgb(color(score)));
+            for dy in 0..cell {
+                for dx in 0..cell {
+                    image.put_pixel(margin_left + j as u32 * cell + dx, i as u32 * cell + dy, pixel);
+                }
+            }
+        }
+    }
+    for i in (0..n).step_by(step) {
+        // rows along the left, columns along the bottom
+        let middle = (i as u32 * cell + cell / 2).saturating_sub(2);
+        draw_label(&mut image, i, 0, middle.min(grid.saturating_sub(5)), false);
+        let middle = (i as u32 * cell + cell / 2).saturating_sub(1);
+        draw_label(&mut image, i, margin_left + middle.min(grid.saturating_sub(3)), grid + 2, true);
+    }
+    image.save_with_format(path, image::ImageFormat::Png)
+}
+
+/// Writes which file each index in the heatmap is, one `index<TAB>file` per line
+pub fn write_legend(path: &Path, names: &[String]) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (i, name) in names.iter().enumerate() {
+        writeln!(file, "{}\t{}", i, name)?;
+    }
+    file.flush()
+}
@@ -0,0 +1,6 @@
+//! Comparing files, shared by the command line and, with the pyo3 feature, the Python module.
+pub mod compare;
+#[cfg(feature = "pyo3")]
+mod python;
+pub mod similarity;
+pub mod split;
@@ -0,0 +1,64 @@
+//! Log messages as JSON, one object per line, for feeding into log pipelines.
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How log messages on stderr look
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(format!("unknown log format \"{}\", expected human or json", s)),
+        }
+    }
+}
+
+struct JsonLogger {
+    level: log::LevelFilter,
+}
+
+impl log::Log for JsonLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |time| time.as_secs_f64());
+        // one write per record, so lines from different threads don't get mixed up
+        let line = format!(
+            "{{\"timestamp\":{:.3},\"level\":\"{}\",\"target\":{},\"message\":{}}}\n",
+            timestamp,
+            record.level(),
+            crate::output::json_string(record.target()),
+            crate::output::json_string(&record.args().to_string())
+        );
+        let _ = std::io::stderr().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging to stderr at `level`
+pub fn init(format: MessageFormat, level: log::LevelFilter) {
+    match format {
+        MessageFormat::Human => pretty_env_logger::formatted_builder().filter_level(level).init(),
+        MessageFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger { level })).expect("logger is only set once");
+            log::set_max_level(level);
+        }
+    }
+}
@@ -1,246 +1,3855 @@
+// bpaf's derive nests a macro call per option, and there are a lot of options
+#![recursion_limit = "256"]
+use ast::AstLanguage;
 use colorgrad::{Color, CustomGradient};
+use cheat_checker::compare::{compare_all, compare_with, Comparing, Throttle, WorkerState};
+use cheat_checker::{similarity, split};
 // #![allow(unused, dead_code)]
-//todo group-by-subfolder? don't compare student's files to themselves.
 use encoding_rs::Encoding;
-use indicatif::ProgressBar;
-use log::LevelFilter::{Debug, Info};
-use owo_colors::{DynColor, Rgb};
-use std::collections::HashMap;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::LevelFilter::{Debug, Info, Warn};
+use owo_colors::Rgb;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{BufWriter, IsTerminal, Read, Write};
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
-use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+mod add;
+mod analysis;
+mod archive;
+mod ast;
+mod cache;
+mod cluster;
+mod git;
+mod heatmap;
+mod logging;
+mod normalize;
+mod output;
+mod profile;
+mod serve;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod watch;
 
 #[derive(Debug, Clone, bpaf::Bpaf)]
 #[bpaf(options, version)]
 struct CliArgs {
-    /// Lower bound for cheat detection.
+    #[bpaf(external(threshold))]
+    threshold: Threshold,
+
+    /// Upper bound for cheat detection.
     ///
-    /// Between 0 and 1, where 1 means identical files.
+    /// With --metric distance it's the lower bound instead. Both bounds are
+    /// inclusive, so a pair scoring exactly this is still shown, and identical
+    /// files get through unless it's below 1 (or above 0 with distance).
     #[bpaf(short, long, argument("SENSITIVITY"))]
-    sensitivity: f64,
+    max_sensitivity: Option<f64>,
+
+    /// Never show identical files, whatever the bounds are
+    ///
+    /// For when the exact copies are known shared files, and only the near
+    /// misses are worth looking at.
+    #[bpaf(long)]
+    exclude_identical: bool,
+
+    /// Different lower bounds for different file types, like py=0.85,java=0.9
+    ///
+    /// Goes by extension. A pair uses the lower bound of its two files, and
+    /// files with an extension that isn't listed use --sensitivity. Only
+    /// changes which pairs get flagged, with a fixed --sensitivity.
+    #[bpaf(long, argument::<String>("MAP"), parse(parse_sensitivity_map), fallback(HashMap::new()))]
+    sensitivity_map: HashMap<String, f64>,
+
+    /// Show and read scores as a similarity or a distance
+    ///
+    /// Distance is 1 - similarity, so 0 means identical files, and lower
+    /// scores are flagged instead of higher ones. The default is similarity.
+    #[bpaf(long, argument("METRIC"), fallback(output::Metric::Similarity))]
+    metric: output::Metric,
+    /// How to score pairs: levenshtein, damerau, lines, winnowing, or another from --help-algorithms
+    ///
+    /// Levenshtein is the edit distance between the files. Damerau is the same,
+    /// but swapping two characters next to each other counts as one edit, and
+    /// it needs a lot more memory for big files. Lines is the share
+    /// of distinct lines the files have in common, which is quicker and
+    /// doesn't care what order they're in. Winnowing is the share of hashed
+    /// runs of tokens they have in common, like MOSS, which is about as quick
+    /// and doesn't mind functions moving around.
+    #[bpaf(long, argument("ALGORITHM"), fallback(similarity::Algorithm::Levenshtein(similarity::Unit::Chars)))]
+    algorithm: similarity::Algorithm,
+
+    /// What levenshtein counts as one character: chars, bytes or graphemes
+    ///
+    /// Graphemes is what a reader would call a character, so an emoji or an
+    /// accented letter made of several chars only counts once. The default is chars.
+    #[bpaf(long, argument("UNIT"), fallback(similarity::Unit::Chars))]
+    unit: similarity::Unit,
+
+    /// How lines scores what two files share: jaccard or containment
+    ///
+    /// Jaccard is what they share out of everything in either file.
+    /// Containment is how much of the smaller file is in the larger one,
+    /// like "80% of A's lines are in B". The default is jaccard.
+    #[bpaf(long, argument("METRIC"), fallback(similarity::Overlap::Jaccard))]
+    overlap_metric: similarity::Overlap,
+
+    /// How much each signal counts for with --algorithm confidence, as EDIT,LINES,SUBSTRING
+    ///
+    /// Edit is levenshtein, lines is lines with jaccard, and substring is the
+    /// longest run of text in both files, out of the shorter one. The score's
+    /// their weighted mean, so only how big they are next to each other
+    /// matters. The default is 0.5,0.3,0.2.
+    #[bpaf(long, argument("WEIGHTS"), optional)]
+    confidence_weights: Option<similarity::Weights>,
+
+    /// How --algorithm sorted-blocks finds the top-level blocks: python, c, cpp, java, js, rust or go
+    ///
+    /// Each file's blocks are sorted by what's in them before comparing, so
+    /// shuffling whole functions around doesn't change the score. Splitting
+    /// goes by indentation or braces, the same as --split-functions.
+    #[bpaf(long, argument("LANG"), optional)]
+    block_language: Option<split::SplitLanguage>,
+
+    /// Shingle size and how many tokens can differ, as K,EDITS, for --algorithm fuzzy-shingles
+    ///
+    /// Each file is cut into every run of K tokens, and a run matches if the
+    /// other file has one with at most EDITS tokens different, so small edits
+    /// on each line don't hide a copy. Every run gets checked against every
+    /// run in the other file, so it's slow on big files, and slower the more
+    /// EDITS there are. 0 edits only finds exact runs. The default is 5,1.
+    #[bpaf(long, argument("K,EDITS"), optional)]
+    fuzzy_shingle: Option<similarity::Shingling>,
 
-    /// Upper bound for cheat detection.
-    #[bpaf(short, long, argument("SENSITIVITY"), fallback(2.0))]
-    max_sensitivity: f64,
     /// Number of calculations to run in parallel.
     ///
-    /// The default is 0, meaning autodetect.
-    #[bpaf(short, long, argument("N"), fallback(0))]
+    /// The default is 0, meaning autodetect, unless CHEAT_CHECKER_JOBS is set.
+    #[bpaf(short, long, env("CHEAT_CHECKER_JOBS"), argument("N"), fallback(0))]
     jobs: usize,
 
-    /// Show additional debugging information.
-    #[bpaf(short, long, switch)]
-    verbose: bool,
+    /// Number of files to load in parallel, if it should be different from --jobs
+    ///
+    /// Loading is mostly waiting on the disk, so a spinning disk might want
+    /// fewer and a fast SSD more. Defaults to the same as --jobs.
+    #[bpaf(long, argument("N"), guard(|n| n.is_none_or(|n| n > 0), "must be at least 1"))]
+    io_jobs: Option<usize>,
+
+    /// Show additional debugging information.
+    ///
+    /// The progress bar also shows the last pair compared.
+    #[bpaf(short, long, switch)]
+    verbose: bool,
+
+    /// Only show warnings and errors on stderr, not what's going on
+    #[bpaf(short, long, switch)]
+    quiet: bool,
+
+    /// Don't show progress bars, just how many pairs were compared at the end
+    ///
+    /// Pairs are still shown as usual. With --quiet, the count isn't shown either.
+    #[bpaf(long)]
+    no_progress: bool,
+
+    /// How messages on stderr look: human, or json for one object per line
+    #[bpaf(long, argument("FORMAT"), fallback(logging::MessageFormat::Human))]
+    log_format: logging::MessageFormat,
+
+    /// Logs all comparisons to this file.
+    #[bpaf(short, long("log"), argument("FILE"))]
+    logfile: Option<PathBuf>,
+
+    /// Format of the logfile: csv, moss for an HTML page like MOSS makes, sheet-csv, or json
+    ///
+    /// The csv format is `score,file,file`. The moss format is a table of
+    /// linked file pairs with their similarity as a percentage, which tools
+    /// that read MOSS results can pick up. The sheet-csv format is csv with
+    /// a header and the files as links, for pasting into a spreadsheet. The
+    /// json format is an array of `{"score":..,"a":..,"b":..}` objects, in the
+    /// same order as csv. Only csv logfiles can be read back in, like by --merge.
+    #[bpaf(long, argument("FORMAT"), fallback(output::LogFormat::Csv))]
+    format: output::LogFormat,
+
+    /// Only log comparisons scoring at least this much.
+    ///
+    /// Meant to be lower than --sensitivity, to keep more in the logfile than
+    /// is shown. The default is logging everything.
+    #[bpaf(long, argument("SENSITIVITY"))]
+    log_threshold: Option<f64>,
+
+    /// Also log just the flagged pairs to this file
+    ///
+    /// Written the same way as --log, in the same order, and can be used
+    /// with it to keep both in one run. --log-threshold doesn't apply.
+    #[bpaf(long, argument("FILE"))]
+    flagged_log: Option<PathBuf>,
+
+    /// Program used to format code before checking, like clang-format or black -q -
+    ///
+    /// Each file is piped through it once when it's loaded, and what it prints
+    /// is compared instead. Improves detection, since changing the format
+    /// won't affect the results anymore. Runs in the shell like --preprocess,
+    /// and what happens to files it fails on is up to --formatter-on-error.
+    #[bpaf(short, long, argument("PROGRAM"))]
+    formatter: Option<String>,
+
+    /// What to do with files --formatter fails on: skip, raw or fail
+    ///
+    /// raw compares them unformatted, with a warning. skip leaves them out
+    /// like files that couldn't be loaded, and fail stops the whole run.
+    #[bpaf(long, argument("ACTION"), fallback(FormatterOnError::Raw))]
+    formatter_on_error: FormatterOnError,
+
+    /// Pipe each file through this shell command, and compare what it prints
+    ///
+    /// For changes cheat_checker doesn't know how to make, like removing
+    /// generated code. The file is on stdin, and its path is in
+    /// CHEAT_CHECKER_FILE. Runs before any other normalizing, and files it
+    /// fails on are skipped.
+    #[bpaf(long, argument("CMD"))]
+    preprocess: Option<String>,
+
+    /// Score each pair normalized a few different ways, and keep the best one
+    ///
+    /// The file as loaded, without whitespace, without comments, and without
+    /// comments with identifiers masked, guessing the language from the
+    /// extension. Catches more, but takes about four times as long. The way
+    /// that scored best is shown next to each pair.
+    #[bpaf(long)]
+    try_all_normalizations: bool,
+
+    /// Also score each pair of functions and each pair of short runs of lines, keeping the best
+    ///
+    /// Catches whole copied files and a single copied function or snippet in
+    /// the same run. Functions are found like --split-functions, for python,
+    /// c, cpp, java, js, rust or go, and the runs are 10 lines long. What the
+    /// best score came from is shown next to each pair. Every piece gets
+    /// compared to every piece of the other file, so it's a lot slower.
+    #[bpaf(long, argument("LANG"))]
+    granular: Option<split::SplitLanguage>,
+
+    /// Also show how unusual each pair's score is for this set of files
+    ///
+    /// Shown as how many standard deviations more similar than the median
+    /// pair it is, for assignments where every pair looks alike because of
+    /// a template. Pairs are only shown once everything's compared.
+    #[bpaf(long)]
+    relative_scores: bool,
+
+    /// Remove whitespace before calculating similarity score
+    #[bpaf(short, long)]
+    trim: bool,
+
+    /// Replace every identifier with `_`, keeping the language's keywords
+    ///
+    /// Cheaper than --ast, and works on any C-like language, but still
+    /// defeats renaming variables. Supports: python, c, cpp, java, js
+    #[bpaf(long, argument("LANG"))]
+    mask_identifiers: Option<normalize::MaskLanguage>,
+
+    /// Replace every number with 0, so only changing constants doesn't help
+    ///
+    /// Works on any language. Where the numbers matter to what the code
+    /// does, like array sizes or lookup tables, files that really are
+    /// different can end up looking more similar.
+    #[bpaf(long)]
+    mask_numbers: bool,
+
+    /// Remove import, include and using lines before anything else
+    ///
+    /// Everyone imports the same libraries, which makes files look more
+    /// alike than they are. Supports: python, c, cpp, java, js
+    #[bpaf(long, argument("LANG"))]
+    strip_imports: Option<normalize::MaskLanguage>,
+
+    /// Normalize files depending on their extension, using a named profile
+    ///
+    /// Built-in profiles: python, c, java, js. Each file gets the first given
+    /// profile that handles its extension, before any other normalization.
+    /// Can be given multiple times.
+    #[bpaf(long, argument("NAME"))]
+    profile: Vec<String>,
+
+    /// Read more profiles from this file, one per line as NAME EXTENSIONS STEPS
+    ///
+    /// Like `kotlin kt,kts line-endings,strip-comments=c,trim`. Steps are
+    /// line-endings, strip-comments=STYLE, mask-identifiers=LANG, keep-indent
+    /// and trim. A profile named like a built-in one replaces it.
+    #[bpaf(long, argument("FILE"))]
+    profiles: Option<PathBuf>,
+
+    /// Normalize whitespace, but keep indentation
+    ///
+    /// For languages like python, where --trim would throw away the structure.
+    /// Indentation becomes one tab per level, trailing whitespace and blank
+    /// lines are removed.
+    #[bpaf(long)]
+    keep_indent: bool,
+
+    /// How many columns a tab is worth when reading indentation
+    #[bpaf(long, argument("N"), fallback(4))]
+    tab_width: usize,
+
+    /// Retry reading a file this many times on errors that might be temporary
+    ///
+    /// Waits a bit longer before each retry. Missing files and permission
+    /// errors are never retried.
+    #[bpaf(long, argument("N"), fallback(0))]
+    read_retries: usize,
+
+    /// Read at most this many files at once while loading
+    ///
+    /// Files are loaded on --jobs threads, this keeps big directories from
+    /// running out of file descriptors when there's lots of threads.
+    #[bpaf(long, argument("N"), fallback(64), guard(|&n| n > 0, "must be at least 1"))]
+    max_open_files: usize,
+
+    /// Load files as they're compared instead of all at the start
+    ///
+    /// Only --cache-files of them are kept in memory at once, so huge sets
+    /// of files fit, but most files get read many times over. Options that
+    /// look at every file's contents at once don't work with it.
+    #[bpaf(long)]
+    no_preload: bool,
+
+    /// How many files --no-preload keeps loaded
+    #[bpaf(long, argument("N"), fallback(256), guard(|&n| n > 0, "must be at least 1"))]
+    cache_files: usize,
+
+    /// Read the files matching some globs with a given encoding, instead of guessing
+    ///
+    /// One `GLOB ENCODING` per line, like `legacy/*.txt windows-1251`. Globs
+    /// are matched against both the file name and the full path, and the
+    /// first match wins. Other files still get their encoding guessed.
+    #[bpaf(long, argument::<PathBuf>("FILE"), parse(read_encoding_map), optional)]
+    encoding_map: Option<EncodingMap>,
+
+    /// Encodings to try in order, like utf-8,windows-1252,shift_jis
+    ///
+    /// Each file is read in the first one it decodes in without errors, and
+    /// gets its encoding guessed if none of them work. Files matching
+    /// --encoding-map use that instead.
+    #[bpaf(long, argument::<String>("LIST"), parse(parse_encodings), fallback(Vec::new()))]
+    encodings: Vec<&'static Encoding>,
+
+    /// Drop the first N lines of each file, like a shared header
+    #[bpaf(long, argument("N"), fallback(0))]
+    skip_head: usize,
+
+    /// Drop the last N lines of each file
+    #[bpaf(long, argument("N"), fallback(0))]
+    skip_tail: usize,
+
+    /// Only compare part of each file, like 0.0-0.8 for the first 80%
+    ///
+    /// Goes by characters, after everything else is done to the file. For
+    /// shared code at the start or end that isn't the same size in every
+    /// file, where --skip-head and --skip-tail don't fit.
+    #[bpaf(long, argument::<String>("START-END"), parse(parse_fraction), optional)]
+    compare_fraction: Option<(f64, f64)>,
+
+    /// Put text in a Unicode normal form, nfc or nfkc, before comparing
+    ///
+    /// Text that looks the same can be made of different characters, like an
+    /// accent that's part of the letter or a separate one after it. nfkc also
+    /// turns things like fullwidth letters into the usual ones.
+    #[bpaf(long, argument("FORM"))]
+    unicode_normalize: Option<normalize::UnicodeForm>,
+
+    /// Replace letters that look like ASCII ones, like a cyrillic `а`, with the ASCII letter
+    ///
+    /// Also removes invisible characters like zero-width spaces. Defeats
+    /// swapping letters for look-alikes to lower the score.
+    #[bpaf(long)]
+    fold_homoglyphs: bool,
+
+    /// Remove characters that couldn't be decoded, instead of comparing them
+    ///
+    /// Undecodable bytes become U+FFFD, so files with lots of them look more
+    /// alike than they are.
+    #[bpaf(long)]
+    strip_replacement_chars: bool,
+
+    /// Skip files where more than this fraction of characters couldn't be decoded
+    ///
+    /// Between 0 and 1, usually binary files or a badly guessed encoding.
+    #[bpaf(long, argument("RATIO"))]
+    max_replacement_ratio: Option<f64>,
+
+    /// Load files as they were at this git revision
+    ///
+    /// Files are still found using the working tree, but their contents are
+    /// read from the commit instead (anything `git rev-parse` understands).
+    #[bpaf(long, argument("REV"))]
+    git_rev: Option<String>,
+
+    /// Compare the structure of the code instead of the text
+    ///
+    /// Parses each file and compares the syntax trees with every identifier
+    /// renamed, so renaming variables and reformatting don't change the score.
+    /// Files that don't parse are compared as text. Supports: python
+    #[bpaf(long, argument("LANG"))]
+    ast: Option<AstLanguage>,
+
+    /// Compare whole directories instead of single files
+    ///
+    /// All the files in a directory are joined together, in order of their
+    /// names, and compared as one document against the other directories.
+    #[bpaf(long)]
+    concat_by_dir: bool,
+
+    /// Compare each top-level function on its own
+    ///
+    /// Splits files into functions (roughly, by indentation or braces) and
+    /// compares functions from different files, to find a single copied function
+    /// in otherwise different files. Supports: python, c, cpp, java, js, rust, go
+    #[bpaf(long, argument("LANG"))]
+    split_functions: Option<split::SplitLanguage>,
+
+    /// Also compare against these files, like submissions from past terms
+    ///
+    /// Given as LABEL:GLOB, the label is shown next to any match so you know
+    /// which baseline it came from. Baseline files aren't compared against
+    /// each other. Can be given multiple times.
+    #[bpaf(long, argument("LABEL:GLOB"))]
+    baseline: Vec<Baseline>,
+
+    /// Only compare what each file added or changed from this one
+    ///
+    /// For assignments where everyone starts from the same file. Each file's
+    /// lines that are also in the base (in the same order) are dropped before
+    /// comparing, so two files that made the same changes score high even when
+    /// the untouched base is most of both. The base itself isn't compared.
+    #[bpaf(long, argument("FILE"))]
+    base: Option<PathBuf>,
+
+    /// Compare every file in this directory against every file in --dir-b
+    ///
+    /// Files on the same side aren't compared against each other, like two
+    /// sections of a class. Each pair is shown with which side its files are from.
+    #[bpaf(long, argument("DIR"))]
+    dir_a: Option<PathBuf>,
+
+    /// The other side for --dir-a
+    #[bpaf(long, argument("DIR"))]
+    dir_b: Option<PathBuf>,
+
+    /// Only compare files matching this glob against files that don't match it
+    ///
+    /// Like --dir-a and --dir-b, for when both kinds of file are in the same
+    /// place and only their names tell them apart, like `*_solution.py`.
+    /// Matched against both the file name and the full path.
+    #[bpaf(long, argument("GLOB"))]
+    query_glob: Option<glob::Pattern>,
+
+    /// Leave out files matching this glob entirely, like a provided library
+    ///
+    /// Matched against both the file name and the full path. Can be given
+    /// multiple times.
+    #[bpaf(long, argument("GLOB"))]
+    ignore_file: Vec<glob::Pattern>,
+
+    /// Don't flag the pairs listed in this file
+    ///
+    /// One pair per line as `file,file`, in either order. Lines copied from
+    /// the logfile work too, so reviewed pairs can be pasted in.
+    #[bpaf(long, argument("FILE"))]
+    ignore_pairs: Option<PathBuf>,
+
+    /// Only compare the pairs listed in this file
+    ///
+    /// Written like --ignore-pairs. The files don't need to be given
+    /// separately, and every listed pair is compared even if it'd normally
+    /// be skipped, like two files from the same group.
+    #[bpaf(long, argument("FILE"))]
+    compare_pairs: Option<PathBuf>,
+
+    /// Write every pair that would be compared to this file, then stop
+    ///
+    /// One `file,file` per line, sorted, so the same files give the same list.
+    /// Split it up and give each part to --compare-pairs to spread a run over
+    /// several machines, then --merge their logfiles.
+    #[bpaf(long, argument("FILE"))]
+    dump_workqueue: Option<PathBuf>,
+
+    /// Don't compare the pairs that are already in this logfile
+    ///
+    /// For picking up a run that was stopped partway, like with ctrl-c or
+    /// --time-budget. The new logfile only has the pairs that were left,
+    /// --merge it with the old one for all of them.
+    #[bpaf(long, argument("LOGFILE"))]
+    resume: Option<PathBuf>,
+
+    /// Compare against the logfile of an earlier run
+    ///
+    /// Shows which pairs got flagged that weren't before, which aren't
+    /// anymore, and how much the scores of flagged pairs changed. Both runs
+    /// need the same --metric.
+    #[bpaf(long, argument("OLDLOG"))]
+    diff_against: Option<PathBuf>,
+
+    /// Use the scores in a csv logfile instead of loading and comparing files
+    ///
+    /// For trying out other reports and output options without comparing
+    /// everything again. Needs the same --metric as the run that wrote it,
+    /// and the files aren't read, so nothing that needs their contents works.
+    #[bpaf(long, argument("LOGFILE"))]
+    from_scores: Option<PathBuf>,
+
+    /// Combine the scores in some csv logfiles, given instead of files to compare
+    ///
+    /// Like --from-scores, but for several runs at once, like one for each
+    /// part of an assignment. With --group-by-parent or --group-regex, pairs
+    /// of groups are combined instead of pairs of files, each run's best
+    /// pair standing in for its groups.
+    #[bpaf(long)]
+    merge: bool,
+
+    /// How --merge combines a pair's scores, max or mean
+    ///
+    /// The mean is only over the runs that have the pair, and finds pairs
+    /// that are similar across every part instead of a lot in one.
+    #[bpaf(long, argument("HOW"), fallback(analysis::Combine::Max))]
+    merge_by: analysis::Combine,
+
+    /// Treat each directory as one student's submission
+    ///
+    /// Files in the same directory won't be compared against each other.
+    #[bpaf(long)]
+    group_by_parent: bool,
+
+    /// With --group-by-parent, group by the directory this many levels up instead
+    ///
+    /// For submissions with their own subdirectories, like 2 for
+    /// `alice/src/main.py` to be in alice's group. Files need to be equally
+    /// deep for that to work, otherwise use --group-regex, like
+    /// 'submissions/([^/]+)/'. The default is 1, the directory the file is in.
+    #[bpaf(long, argument("N"), fallback(1), guard(|&n| n > 0, "--group-depth needs to be at least 1"))]
+    group_depth: usize,
+
+    /// Don't compare files in the same directory against each other
+    ///
+    /// Just skips those pairs, like --group-by-parent does, but without
+    /// making the directories into groups for the reports and output.
+    #[bpaf(long)]
+    skip_same_dir: bool,
+
+    /// Treat files as one student's submission when this regex finds the same thing in their paths
+    ///
+    /// Uses the first capture group if there is one, like 'student(\d+)' for
+    /// files named `hw3_student42_main.py`. Files it doesn't match aren't in
+    /// any group.
+    #[bpaf(long, argument("REGEX"))]
+    group_regex: Option<regex::Regex>,
+
+    /// After comparing, show each group's closest match in another group
+    ///
+    /// Most similar first, so the students that look most like someone else
+    /// are at the top. Needs --group-by-parent or --group-regex.
+    #[bpaf(long)]
+    group_summary: bool,
+
+    /// After comparing, draw a bar chart of how many pairs got each score
+    ///
+    /// Pairs that copied usually show up as a separate bump near the top.
+    #[bpaf(long)]
+    ascii_histogram: bool,
+
+    /// Only compare files with the same name, like everyone's `solution.py`
+    #[bpaf(long)]
+    same_basename: bool,
+
+    /// Importance of files matching a glob, like `solution.*=2.0`
+    ///
+    /// Matched against both the file name and the full path. A pair's weight
+    /// is the larger of its two files' weights, and is shown next to the score.
+    /// Can be given multiple times, the first matching rule wins.
+    #[bpaf(long, argument("GLOB=WEIGHT"))]
+    weight: Vec<WeightRule>,
+
+    /// Show lines that appear in more than N files
+    ///
+    /// Whitespace is collapsed before comparing lines, and very short ones
+    /// are ignored. Shown before the pairwise comparison starts.
+    #[bpaf(long, argument("N"))]
+    common_lines: Option<usize>,
+
+    /// Only show the K most similar files to each file
+    ///
+    /// Instead of every flagged pair, prints each file followed by its closest
+    /// matches in the sensitivity window, so the output grows with the number
+    /// of files rather than the number of pairs.
+    #[bpaf(long, argument("K"))]
+    knn: Option<usize>,
+
+    /// Instead of pairs, show one line per file with whether it got flagged
+    ///
+    /// Each line is `yes` or `no`, the file's best score against any other
+    /// file (or `-` if it wasn't compared to anything), and the file. Meant
+    /// for filling in a pass/fail column.
+    #[bpaf(long)]
+    flag_files: bool,
+
+    /// Instead of pairs, show each flagged file with everything it matched
+    ///
+    /// One block per file, its matches under it most similar first, so a file
+    /// that matches lots of others only comes up once. A pair shows up under
+    /// both of its files.
+    #[bpaf(long)]
+    aggregate_by_file: bool,
+
+    /// Only show the most similar pair, and nothing else
+    ///
+    /// Every pair is still compared, but the sensitivity doesn't matter and
+    /// no reports or logfiles are written. A quick check for whether there's
+    /// any copying at all.
+    #[bpaf(long)]
+    worst: bool,
+
+    /// Also show each file's entropy, lowest first, marking unusually low ones
+    ///
+    /// Entropy is how unpredictable a file's characters are, so repetitive
+    /// filler and generated files stand out even when they aren't like any
+    /// other file. Files further below the first quartile than 1.5 times the
+    /// interquartile range are marked.
+    #[bpaf(long)]
+    entropy: bool,
+
+    /// Show at most N flagged pairs with any one file, keeping the most similar
+    ///
+    /// So one file that everyone copied from doesn't drown out everything
+    /// else. The logfile and reports still have every pair. Nothing is shown
+    /// until everything's been compared.
+    #[bpaf(long, argument("N"))]
+    max_pairs_per_file: Option<usize>,
+
+    /// Only flag pairs where the smaller file has at least N characters
+    ///
+    /// Counted after normalizing. Two tiny files being similar usually doesn't
+    /// mean anything. They're still compared and logged.
+    #[bpaf(long, argument("N"), fallback(0))]
+    min_pair_length: usize,
+
+    /// Round scores to N decimal places as soon as they're worked out
+    ///
+    /// Affects everything, not just what's shown: thresholds, sorting, ties
+    /// and the logfile all see the rounded scores, so scores that only differ
+    /// by floating point noise sort together.
+    #[bpaf(long, argument("N"))]
+    round_to: Option<i32>,
+
+    /// Order to show pairs in: score-desc, score-asc or file
+    ///
+    /// Applies to the logfile as well. Without it, pairs are shown as they're
+    /// found and the logfile has the most similar first. With it, nothing is
+    /// shown until everything's been compared. --output-style grouped is
+    /// always sorted, most similar first unless this says otherwise.
+    #[bpaf(long, argument("ORDER"))]
+    sort: Option<output::SortOrder>,
+
+    /// Show pairs N at a time, waiting for enter between pages
+    ///
+    /// When it isn't run from a terminal, or with --output, the pages are just
+    /// split up by a separator line instead. Like --sort, nothing is shown until
+    /// everything's been compared.
+    #[bpaf(long, argument("N"), guard(|n| n.is_none_or(|n| n > 0), "--page needs at least 1 pair per page"))]
+    page: Option<usize>,
+
+    /// Show the longest piece of text each flagged pair has in common
+    ///
+    /// Usually the part that got copied. Only the first few lines of long
+    /// matches are shown.
+    #[bpaf(long)]
+    show_lcs: bool,
+
+    /// Show which lines of each flagged pair match, in the files as they are
+    ///
+    /// The few longest runs of matching lines, numbered like in the original
+    /// files instead of after normalizing. Lines match when they only differ
+    /// by whitespace. Doesn't work with --split-functions or --concat-by-dir.
+    #[bpaf(long)]
+    show_ranges: bool,
+
+    /// Group similar files into clusters instead of comparing every pair
+    ///
+    /// Much faster on huge sets of files, but approximate: it goes by shared
+    /// lines, and can miss pairs under about 0.5 similar. Shows each cluster
+    /// with roughly how similar its files are.
+    #[bpaf(long)]
+    cluster_lsh: bool,
+
+    /// Only find files that are exactly the same, instead of comparing every pair
+    ///
+    /// Compared after normalizing, so --trim and the like still apply. Quick
+    /// even for huge sets of files, and shows groups instead of pairs.
+    #[bpaf(long)]
+    exact_only: bool,
+
+    /// Score each file against every other group as a whole, instead of pair by pair
+    ///
+    /// Each group's lines are pooled together, and each file is shown with the
+    /// other group whose pool has the most of its lines. A quick screen for
+    /// whole groups copying from each other, needs --group-by-parent or
+    /// --group-regex.
+    #[bpaf(long)]
+    centroids: bool,
+
+    /// Find which files contain this text, like a solution posted online
+    ///
+    /// Each file is scored by how much of the snippet's lines it has, and
+    /// the ones at or above --sensitivity are shown, best first. The snippet
+    /// is normalized the same way as the files.
+    #[bpaf(long, argument("TEXT"))]
+    snippet: Option<String>,
+
+    /// Like --snippet, but reads the snippet from FILE
+    #[bpaf(long, argument("FILE"))]
+    snippet_file: Option<PathBuf>,
+
+    /// Only compare a random sample of N pairs
+    ///
+    /// For a quick look at a big set of files, the results won't include
+    /// every similar pair.
+    #[bpaf(long, argument("N"))]
+    sample: Option<usize>,
+
+    /// Only load and compare a random sample of K files, with every pair of them
+    ///
+    /// For a quick idea of how much copying there is in a huge set of files,
+    /// when even loading them all takes too long. Baselines are always included.
+    #[bpaf(long, argument("K"))]
+    file_sample: Option<usize>,
+
+    /// Seed for anything random, so runs can be reproduced
+    #[bpaf(long, argument("SEED"), fallback(0))]
+    seed: u64,
+
+    /// Format for each flagged pair, like '{score} {a} {b}'
+    ///
+    /// Placeholders: {score} (always the similarity), {distance} (1 - score), {a}, {b}, {group_a},
+    /// {group_b}, {weight} and {baseline}. Use {{ and }} for literal braces,
+    /// and \t or \n for tabs and newlines.
+    #[bpaf(long, argument("TEMPLATE"))]
+    template_str: Option<output::Template>,
+
+    /// Output for automated checks
+    ///
+    /// Only prints the flagged pairs, with paths relative to the directory
+    /// they're all in, and exits with 1 if there were any.
+    #[bpaf(long)]
+    ci: bool,
+
+    /// How to show flagged pairs: plain, or grouped
+    ///
+    /// Grouped is a table of group, file, group, file and score, with a
+    /// header, for pasting into a spreadsheet. Groups come from
+    /// --group-by-parent or --group-regex.
+    #[bpaf(long, argument("STYLE"), fallback(output::OutputStyle::Plain))]
+    output_style: output::OutputStyle,
+
+    /// How to show paths: cwd, absolute, or common
+    ///
+    /// Normal output shows absolute paths, and --ci, grouped output and
+    /// reports show them from the directory all the files are in. This
+    /// picks one for all of them. The csv logfile always has absolute paths,
+    /// so it can be read back in.
+    #[bpaf(long, argument("STYLE"))]
+    paths: Option<output::PathStyle>,
+
+    /// Write the results to this file instead of stdout
+    #[bpaf(short, long, argument("FILE"))]
+    output: Option<PathBuf>,
+
+    /// Color for the least similar scores shown, like #7cf92a
+    ///
+    /// Scores are colored on a gradient from this to --color-threshold-high.
+    /// Set NO_COLOR to turn colors off.
+    #[bpaf(long, argument::<String>("HEX"), parse(parse_hex_color), fallback(Rgb(124, 249, 42)))]
+    color_threshold_low: Rgb,
+
+    /// Color for the most similar scores shown, like #ff1035
+    #[bpaf(long, argument::<String>("HEX"), parse(parse_hex_color), fallback(Rgb(255, 16, 53)))]
+    color_threshold_high: Rgb,
+
+    /// Show scores in bold
+    #[bpaf(long)]
+    bold: bool,
+
+    /// Write the flagged pairs to this file as a JUnit XML report
+    #[bpaf(long, argument("FILE"))]
+    junit: Option<PathBuf>,
+
+    /// Write each file's originality to this file, as `path,originality` lines
+    ///
+    /// Originality is 1 minus the file's best similarity to any other file
+    /// it was compared to, so lower is more suspicious, whatever --metric is.
+    /// Files in the same group aren't compared, so they don't count against
+    /// each other. Files that weren't compared to anything are left blank.
+    #[bpaf(long, argument("FILE"))]
+    originality: Option<PathBuf>,
+
+    /// Also write a short JSON summary of the run to this file
+    ///
+    /// How many files and pairs there were, the sensitivity, how long each
+    /// part took, and the most similar flagged pairs. The normal output
+    /// doesn't change.
+    #[bpaf(long, argument("FILE"))]
+    summary_json: Option<PathBuf>,
+
+    /// Write a record of exactly what ran to this file, as JSON
+    ///
+    /// Has the version, the arguments and every option, each input file with
+    /// the SHA-256 of its contents, and the flagged pairs. The last field is
+    /// the SHA-256 of everything before it, so changes to the file show up.
+    #[bpaf(long, argument("FILE"))]
+    manifest: Option<PathBuf>,
+
+    /// Also draw every pair's score as a heatmap, and save it to FILE as a PNG
+    ///
+    /// Similar files are put next to each other, so groups of them show up as
+    /// bright blocks. Files are labeled by number, and FILE.legend.txt says
+    /// which number is which file. Pairs that weren't compared are gray.
+    #[bpaf(long, argument("FILE"))]
+    heatmap: Option<PathBuf>,
+
+    /// Add this run's files, groups and scores to a SQLite database
+    ///
+    /// The database is created if it doesn't exist, and each run gets its
+    /// own id. Only available when built with the sqlite feature.
+    #[bpaf(long, argument("FILE"))]
+    sqlite: Option<PathBuf>,
+
+    /// After comparing, show the flagged pairs at http://127.0.0.1:PORT/
+    ///
+    /// The page is the same as --format moss makes, and /results.json has
+    /// the pairs as JSON. Keeps serving until ctrl-c.
+    #[bpaf(long, argument("PORT"))]
+    serve: Option<u16>,
+
+    /// Put more about each pair in the JSON results
+    ///
+    /// Adds the distance and both files' lengths, plus the matching lines
+    /// with --show-ranges and the normalization that scored best with
+    /// --try-all-normalizations.
+    #[bpaf(long)]
+    json_details: bool,
+
+    /// Write each file as it'll be compared into DIR, then exit
+    ///
+    /// After all the normalizing, so you can see what the comparison sees.
+    /// Files are laid out in DIR the same way as they are on disk.
+    #[bpaf(long, argument("DIR"))]
+    prepare_only: Option<PathBuf>,
+
+    /// Show how long each part of the run took
+    #[bpaf(long)]
+    timings: bool,
+
+    /// Sample where the comparisons spend their time, and save it to FILE as a flamegraph
+    ///
+    /// An SVG that shows which functions were running the most, like the
+    /// algorithm, hashing or waiting on locks. Only available when built with
+    /// the profiling feature, which only works on unix.
+    #[bpaf(long, argument("FILE"))]
+    profile_output: Option<PathBuf>,
+
+    /// Time comparing N made up files instead, then exit
+    ///
+    /// Shows how many comparisons per second this machine manages, with the
+    /// chosen --algorithm and --jobs. Uses --seed, and --benchmark-size for
+    /// how long each file is.
+    #[bpaf(long, argument("N"))]
+    benchmark: Option<usize>,
+
+    /// Stop starting new comparisons after this many seconds
+    ///
+    /// Whatever finished in time gets reported, with a warning that it's not
+    /// everything. The slowest pairs are compared first.
+    #[bpaf(long, argument("SECONDS"), guard(|s| s.is_none_or(|s| s.is_finite() && s > 0.0), "must be more than 0"))]
+    time_budget: Option<f64>,
+
+    /// Make at most N comparisons a second, over all the threads together
+    ///
+    /// For running in the background on a shared machine. The run takes at
+    /// least as long as the number of pairs divided by N, however fast the
+    /// comparisons are.
+    #[bpaf(long, argument("N"), guard(|n| n.is_none_or(|n| n.is_finite() && n > 0.0), "must be more than 0"))]
+    max_cps: Option<f64>,
+
+    /// Characters in each made up file for --benchmark
+    #[bpaf(long, argument("CHARS"), fallback(2000))]
+    benchmark_size: usize,
+
+    /// Score FILE against a few slightly changed copies of itself, then exit
+    ///
+    /// Uses all the other options, so you can see what a small change
+    /// scores before picking a sensitivity.
+    #[bpaf(long, argument("FILE"))]
+    self_check: Option<PathBuf>,
+
+    /// Go through everything about how A and B compare, then exit
+    ///
+    /// Shows their lengths, what they score under every algorithm and with
+    /// each normalization, the longest text they share and a diff.
+    #[bpaf(long, argument::<String>("A,B"), parse(split_pair), optional)]
+    explain: Option<(PathBuf, PathBuf)>,
+
+    /// List the algorithms, what they're good at and how slow they are, then exit
+    // only here for --help, main looks for it before parsing
+    #[bpaf(long("help-algorithms"))]
+    _help_algorithms: bool,
+
+    /// Print the version, build and the options as they'll be used, then exit
+    #[bpaf(long, hide)]
+    debug_info: bool,
+
+    /// Run the comparisons again on a single thread and check the scores match
+    #[bpaf(long, hide)]
+    verify_serial: bool,
+
+    /// Stop with an error if a glob is invalid or doesn't match any files
+    ///
+    /// Normally those only get a warning and are skipped.
+    #[bpaf(long)]
+    strict_globs: bool,
+
+    /// Stop with an error unless there are exactly N files to compare
+    ///
+    /// Counted after all the globs, ignore files and filters, baselines
+    /// included. For automated runs where a moved directory would otherwise
+    /// quietly compare the wrong number of submissions.
+    #[bpaf(long, argument("N"))]
+    expect_files: Option<usize>,
+
+    /// Also read files to compare from stdin, one per line
+    ///
+    /// These are taken literally, not as globs.
+    #[bpaf(long)]
+    stdin_list: bool,
+
+    /// Paths from --stdin-list are separated by null bytes instead of lines
+    ///
+    /// For paths that might contain newlines, like from `find -print0`.
+    #[bpaf(short('0'), long)]
+    null: bool,
+
+    /// Only compare this one file, against the files given with --against
+    ///
+    /// For checking submissions one at a time as they come in. With --store,
+    /// scores are remembered so pairs are never compared twice.
+    #[bpaf(long, argument("FILE"))]
+    add: Option<PathBuf>,
+
+    /// Files or globs of files to compare the --add file against
+    #[bpaf(long, argument("GLOB"))]
+    against: Vec<PathBuf>,
+
+    /// Remember scores from --add in this file, and reuse them next time
+    ///
+    /// Pairs are recognized by the files' contents, so changed files are
+    /// compared again. Use the same options every time, the scores depend on them.
+    #[bpaf(long, argument("FILE"))]
+    store: Option<PathBuf>,
+
+    /// Keep watching this directory, comparing new files as they show up
+    ///
+    /// Each new or changed file is compared against every file seen so far,
+    /// including the given FILEs, and matches are printed right away. Runs
+    /// until interrupted. Needs a fixed --sensitivity.
+    #[bpaf(long, argument("DIR"))]
+    watch: Option<PathBuf>,
+
+    /// With --watch, also keep checking files already seen for changes
+    ///
+    /// For files that keep getting appended to, like logs, and files outside
+    /// the watched directory. Any file whose size or modification time
+    /// changes is read again, and only its pairs are compared again.
+    #[bpaf(long)]
+    follow: bool,
+
+    /// Files or globs of files to compare.
+    ///
+    /// Tar archives (.tar, .tar.gz, .tgz, .tar.bz2 or .tar.xz) are read like
+    /// directories, so each file in them is compared.
+    #[bpaf(positional("FILE"))]
+    files: Vec<PathBuf>,
+}
+
+/// How the lower bound for cheat detection is decided
+#[derive(Debug, Clone, bpaf::Bpaf)]
+enum Threshold {
+    Fixed {
+        /// Lower bound for cheat detection.
+        ///
+        /// Between 0 and 1, where 1 means identical files (or 0, with --metric distance).
+        #[bpaf(short, long, argument("SENSITIVITY"))]
+        sensitivity: f64,
+    },
+    Percentile {
+        /// Set the lower bound to the P-th percentile of all scores.
+        ///
+        /// Between 0 and 100, so 99 flags the most similar 1% of pairs
+        /// (or 1 does, with --metric distance).
+        /// Nothing is shown until every comparison is done.
+        #[bpaf(long, argument("P"), guard(|p| (0.0..=100.0).contains(p), "must be between 0 and 100"))]
+        percentile: f64,
+    },
+}
+
+/// Whether a similarity is within --max-sensitivity, counting a score right on
+/// it. With --exclude-identical, identical files never are.
+fn under_max(score: f64, max_similarity: f64, exclude_identical: bool) -> bool {
+    score <= max_similarity && !(exclude_identical && score >= 1.0)
+}
+
+/// Suggest --group-by-parent when at least this fraction of files share a directory
+const SHARED_PARENT_RATIO: f64 = 0.5;
+
+/// Warn when more than this fraction of pairs are flagged
+const MAX_FLAGGED_RATIO: f64 = 0.5;
+
+/// With fewer pairs than this, flagging most of them can just be chance
+const MIN_PAIRS_FOR_RATIO: usize = 10;
+
+/// Lines of the longest common text shown for each pair with --show-lcs
+const LCS_SHOWN_LINES: usize = 10;
+
+/// Matching runs of lines shown for each pair with --show-ranges
+const RANGES_SHOWN: usize = 3;
+
+/// When this much of what one glob matched was matched by another too, say so
+const GLOB_OVERLAP_RATIO: f64 = 0.5;
+
+/// With more pairs than this in the logfile, sorting them is worth spreading across threads
+#[cfg(feature = "rayon")]
+const PARALLEL_SORT_MIN: usize = 100_000;
+
+/// How many times a second --profile-output looks at what's running
+#[cfg(feature = "profiling")]
+const PROFILE_FREQUENCY: i32 = 1000;
+
+/// Files trimmed down to less than this were probably mostly whitespace,
+/// and anything will look similar to them
+const MIN_TRIMMED_LENGTH: usize = 10;
+
+/// How long each part of the run took
+struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+    start: Instant,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings { phases: Vec::new(), start: Instant::now() }
+    }
+}
+
+impl Timings {
+    /// Ends a phase that started when the last one finished
+    fn finish(&mut self, phase: &'static str) {
+        self.phases.push((phase, self.start.elapsed()));
+        self.start = Instant::now();
+    }
+
+    fn print(&self) {
+        let total: Duration = self.phases.iter().map(|(_, time)| *time).sum();
+        let width = self.phases.iter().map(|(phase, _)| phase.len()).max().unwrap_or(0);
+        eprintln!("{:width$}  {:>10}", "Phase", "Seconds", width = width);
+        for (phase, time) in &self.phases {
+            eprintln!(
+                "{:width$}  {:>10.3}  {:>5.1}%",
+                phase,
+                time.as_secs_f64(),
+                time.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0,
+                width = width
+            );
+        }
+        eprintln!("{:width$}  {:>10.3}", "Total", total.as_secs_f64(), width = width);
+    }
+}
+
+/// Everything that's useful to have in a bug report
+fn print_debug_info(opts: &CliArgs) {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    println!(
+        "build: {}, {}-{}",
+        if cfg!(debug_assertions) { "debug" } else { "release" },
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    // whether each cargo feature is built in
+    println!(
+        "features: sqlite={}, rayon={}, profiling={}, pyo3={}",
+        cfg!(feature = "sqlite"),
+        cfg!(feature = "rayon"),
+        cfg!(feature = "profiling"),
+        cfg!(feature = "pyo3")
+    );
+    match thread::available_parallelism() {
+        Ok(cpus) => println!("cpus: {}", cpus),
+        Err(err) => println!("cpus: unknown ({})", err),
+    }
+    println!("options: {:#?}", opts);
+}
+
+/// Compares made up files, and prints how fast it went
+fn benchmark(count: usize, opts: &CliArgs) {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(opts.seed);
+    // start from one file and mutate it, so the scores are somewhat realistic
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz(){}[];=+-*/ \n";
+    let original: Vec<u8> = (0..opts.benchmark_size)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())])
+        .collect();
+    let files: HashMap<PathBuf, String> = (0..count)
+        .map(|i| {
+            let mut text = original.clone();
+            for _ in 0..text.len() / 10 {
+                let at = rng.gen_range(0..text.len());
+                text[at] = ALPHABET[rng.gen_range(0..ALPHABET.len())];
+            }
+            (PathBuf::from(format!("file{}", i)), String::from_utf8(text).expect("alphabet is ascii"))
+        })
+        .collect();
+    let scorer = opts.algorithm.scorer();
+    let prepared: HashMap<PathBuf, similarity::PreparedFile> =
+        files.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+    let workqueue: Vec<(&PathBuf, &PathBuf)> = files
+        .keys()
+        .flat_map(|x| files.keys().filter(move |y| x < *y).map(move |y| (x, y)))
+        .collect();
+    let pairs = workqueue.len();
+    let stop = AtomicBool::new(false);
+    let start = Instant::now();
+    let comparing = Comparing::new(opts.algorithm, similarity::count_rarity(opts.algorithm, prepared.values()));
+    compare_all(workqueue, &prepared, &comparing, opts.jobs, &stop, |_, _, _| ());
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{:?} on {} jobs: {} comparisons of {} characters in {:.3}s, {:.1} comparisons/s",
+        opts.algorithm,
+        opts.jobs,
+        pairs,
+        opts.benchmark_size,
+        elapsed,
+        pairs as f64 / elapsed.max(f64::EPSILON)
+    );
+}
+
+/// Scores a file against copies of itself with small, known changes, for a
+/// feel of what scores mean with the current options
+fn self_check(path: &Path, opts: &CliArgs, loading: &Loading) -> anyhow::Result<()> {
+    // loaded like any other file, the changes are made before normalizing like they would be by hand
+    let original = load_unnormalized(path, opts)?
+        .ok_or_else(|| anyhow::anyhow!("{} was skipped while loading", path.to_string_lossy()))?;
+    // the most common identifier-looking word, to rename
+    let mut words: HashMap<&str, usize> = HashMap::new();
+    for word in original.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        if word.len() > 1 && !word.starts_with(|c: char| c.is_ascii_digit()) && !normalize::is_keyword(word) {
+            *words.entry(word).or_default() += 1;
+        }
+    }
+    let common_word = words.into_iter().max_by_key(|&(word, count)| (count, std::cmp::Reverse(word)));
+    let mut changes: Vec<(String, String)> = vec![
+        ("identical copy".to_string(), original.clone()),
+        (
+            "indentation doubled".to_string(),
+            original
+                .lines()
+                .map(|line| {
+                    let content = line.trim_start();
+                    let indent = &line[..line.len() - content.len()];
+                    format!("{}{}{}\n", indent, indent, content)
+                })
+                .collect(),
+        ),
+        (
+            "every 5th line removed".to_string(),
+            original
+                .lines()
+                .enumerate()
+                .filter(|(i, _)| i % 5 != 4)
+                .map(|(_, line)| format!("{}\n", line))
+                .collect(),
+        ),
+    ];
+    if let Some((word, _)) = common_word {
+        let pattern = regex::Regex::new(&format!(r"\b{}\b", regex::escape(word)))?;
+        let renamed = pattern.replace_all(&original, "renamed_thing").into_owned();
+        changes.push((format!("\"{}\" renamed", word), renamed));
+    }
+    let scorer = opts.algorithm.scorer();
+    let normalized = normalize_text(path, original.clone(), opts, loading);
+    let prepared = scorer.prepare(&normalized);
+    let width = changes.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, changed) in changes {
+        let changed = normalize_text(path, changed, opts, loading);
+        let changed = scorer.prepare(&changed);
+        // weighed like a run with just the two files would be
+        let rarity = similarity::count_rarity(opts.algorithm, [&prepared, &changed].into_iter());
+        let score = opts.algorithm.scorer_with(rarity).score(&prepared, &changed);
+        println!("{:width$}  {:.6}", name, opts.metric.convert(score), width = width);
+    }
+    Ok(())
+}
+
+/// Reads a color like `#ff1035`, the `#` is optional
+fn parse_hex_color(s: String) -> Result<Rgb, String> {
+    let hex = s.strip_prefix('#').unwrap_or(&s);
+    let channel = |i: usize| hex.get(i..i + 2).and_then(|channel| u8::from_str_radix(channel, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(Rgb(r, g, b)),
+        _ => Err(format!("expected a color like #ff1035, got \"{}\"", s)),
+    }
+}
+
+/// Describes every algorithm for --help-algorithms
+fn print_algorithms() {
+    for description in similarity::ALGORITHMS {
+        println!("{}: {}", description.name, description.summary);
+        println!("    reordering: {}", description.reordering);
+        println!("    renaming:   {}", description.renaming);
+        println!("    cost:       {}", description.cost);
+    }
+    println!();
+    println!("--unit picks chars, bytes or graphemes for levenshtein and damerau,");
+    println!("and --overlap-metric picks jaccard or containment for lines.");
+}
+
+/// Splits the `a,b` given to --explain
+fn split_pair(s: String) -> Result<(PathBuf, PathBuf), String> {
+    match s.split_once(',') {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() => Ok((PathBuf::from(a), PathBuf::from(b))),
+        _ => Err(format!("expected two files like a.py,b.py, got \"{}\"", s)),
+    }
+}
+
+/// Parses the comma separated encodings given to --encodings
+fn parse_encodings(s: String) -> Result<Vec<&'static Encoding>, String> {
+    s.split(',')
+        .map(|label| Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| format!("unknown encoding \"{}\"", label)))
+        .collect()
+}
+
+/// Parses the `ext=sensitivity` list given to --sensitivity-map
+fn parse_sensitivity_map(s: String) -> Result<HashMap<String, f64>, String> {
+    s.split(',')
+        .map(|rule| {
+            let (extension, sensitivity) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("expected EXT=SENSITIVITY, got \"{}\"", rule))?;
+            let sensitivity = sensitivity
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("\"{}\" isn't a number", sensitivity))?;
+            let extension = extension.trim().trim_start_matches('.').to_ascii_lowercase();
+            Ok((extension, sensitivity))
+        })
+        .collect()
+}
+
+/// Parses the `start-end` given to --compare-fraction
+fn parse_fraction(s: String) -> Result<(f64, f64), String> {
+    let parsed = s.split_once('-').and_then(|(start, end)| Some((start.parse::<f64>().ok()?, end.parse::<f64>().ok()?)));
+    match parsed {
+        Some((start, end)) if 0.0 <= start && start < end && end <= 1.0 => Ok((start, end)),
+        _ => Err(format!("expected two fractions from 0 to 1 like 0.0-0.8, got \"{}\"", s)),
+    }
+}
+
+/// Damerau needs memory for every pair of characters, past this --explain leaves it out
+const EXPLAIN_DAMERAU_MAX: usize = 20_000;
+
+/// Prints everything there is to know about how two files compare
+fn explain(a: &Path, b: &Path, opts: &CliArgs, loading: &Loading) -> anyhow::Result<()> {
+    let load = |path: &Path| -> anyhow::Result<String> {
+        load_unnormalized(path, opts)?
+            .ok_or_else(|| anyhow::anyhow!("{} was skipped while loading", path.to_string_lossy()))
+    };
+    let (raw_a, raw_b) = (load(a)?, load(b)?);
+    let (text_a, text_b) = (
+        normalize_text(a, raw_a.clone(), opts, loading),
+        normalize_text(b, raw_b.clone(), opts, loading),
+    );
+    let score = |algorithm: similarity::Algorithm, x: &str, y: &str| {
+        let scorer = algorithm.scorer();
+        // the blocks are usually sorted while loading, but not when it's not the one in use
+        let (x, y) = match algorithm {
+            similarity::Algorithm::SortedBlocks(language) => {
+                (Cow::Owned(split::sorted_blocks(language, x)), Cow::Owned(split::sorted_blocks(language, y)))
+            }
+            _ => (Cow::Borrowed(x), Cow::Borrowed(y)),
+        };
+        let (x, y) = (scorer.prepare(&x), scorer.prepare(&y));
+        let scorer = algorithm.scorer_with(similarity::count_rarity(algorithm, [&x, &y].into_iter()));
+        opts.metric.convert(scorer.score(&x, &y))
+    };
+    println!("A: {}", a.to_string_lossy());
+    println!("B: {}", b.to_string_lossy());
+
+    println!("\nLengths:");
+    for (name, raw, text) in [("A", &raw_a, &text_a), ("B", &raw_b, &text_b)] {
+        println!(
+            "  {}  {} lines, {} characters, {} after normalizing",
+            name,
+            raw.lines().count(),
+            raw.chars().count(),
+            text.chars().count()
+        );
+    }
+
+    println!("\nEvery algorithm, * is the one in use:");
+    let mut algorithms: Vec<(String, similarity::Algorithm)> = Vec::new();
+    for (unit, name) in [(similarity::Unit::Chars, "chars"), (similarity::Unit::Bytes, "bytes"), (similarity::Unit::Graphemes, "graphemes")] {
+        algorithms.push((format!("levenshtein, {}", name), similarity::Algorithm::Levenshtein(unit)));
+        algorithms.push((format!("damerau, {}", name), similarity::Algorithm::Damerau(unit)));
+    }
+    algorithms.push(("lines, jaccard".to_string(), similarity::Algorithm::Lines(similarity::Overlap::Jaccard)));
+    algorithms.push(("lines, containment".to_string(), similarity::Algorithm::Lines(similarity::Overlap::Containment)));
+    algorithms.push((
+        "confidence".to_string(),
+        similarity::Algorithm::Confidence(opts.confidence_weights.unwrap_or_default()),
+    ));
+    algorithms.push(("structure".to_string(), similarity::Algorithm::Structure));
+    // there's no other files to tell what's common, so it's only weighed over these two
+    algorithms.push(("tf-idf, over just these two".to_string(), similarity::Algorithm::TfIdf));
+    algorithms.push(("winnowing".to_string(), similarity::Algorithm::Winnowing));
+    algorithms.push((
+        "fuzzy-shingles".to_string(),
+        similarity::Algorithm::FuzzyShingles(opts.fuzzy_shingle.unwrap_or_default()),
+    ));
+    algorithms.push((
+        "sorted-blocks".to_string(),
+        similarity::Algorithm::SortedBlocks(opts.block_language.unwrap_or(split::SplitLanguage::Python)),
+    ));
+    let width = algorithms.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let too_long = text_a.len().max(text_b.len()) > EXPLAIN_DAMERAU_MAX;
+    for (name, algorithm) in algorithms {
+        let marker = if algorithm == opts.algorithm { '*' } else { ' ' };
+        if too_long && matches!(algorithm, similarity::Algorithm::Damerau(_)) {
+            println!("{} {:width$}  (too long)", marker, name, width = width);
+        } else {
+            println!("{} {:width$}  {:.6}", marker, name, score(algorithm, &text_a, &text_b), width = width);
+        }
+    }
+
+    println!("\nWith each normalization, scored by {:?}:", opts.algorithm);
+    let mut normalizations = vec![("none", raw_a.clone(), raw_b.clone()), ("as chosen", text_a.clone(), text_b.clone())];
+    let (variants_a, variants_b) = (normalize::variants(a, &raw_a), normalize::variants(b, &raw_b));
+    for ((name, x), y) in normalize::VARIANTS[1..].iter().zip(variants_a).zip(variants_b) {
+        normalizations.push((name, x, y));
+    }
+    let width = normalizations.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0);
+    for (name, x, y) in &normalizations {
+        println!("  {:width$}  {:.6}", name, score(opts.algorithm, x, y), width = width);
+    }
+
+    let common = analysis::longest_common_substring(&text_a, &text_b);
+    println!("\nLongest common text, {} characters:", common.chars().count());
+    for line in common.lines() {
+        println!("  | {}", line);
+    }
+
+    println!("\nDiff, after normalizing:");
+    for (change, line) in analysis::line_diff(&text_a, &text_b) {
+        println!("  {} {}", change, line);
+    }
+    Ok(())
+}
+
+/// Finds the P-th percentile of some scores, using the nearest rank
+fn percentile(scores: impl Iterator<Item = f64>, p: f64) -> Option<f64> {
+    let mut scores: Vec<f64> = scores.collect();
+    if scores.is_empty() {
+        return None;
+    }
+    scores.sort_unstable_by(|a, b| a.partial_cmp(b).expect("Couldn't compare two scores"));
+    let rank = (p / 100.0 * scores.len() as f64).ceil() as usize;
+    Some(scores[rank.clamp(1, scores.len()) - 1])
+}
+
+/// How many of the most similar of `count` scores `percentile_from_top` needs
+fn top_needed(count: usize, p: f64, metric: output::Metric) -> usize {
+    // the share of pairs past the percentile, the most similar ones either way
+    let share = match metric {
+        output::Metric::Similarity => 1.0 - p / 100.0,
+        output::Metric::Distance => p / 100.0,
+    };
+    (share * count as f64).ceil() as usize + 1
+}
+
+/// Like `percentile`, but from only the most similar of `count` scores, most similar first
+fn percentile_from_top(top: impl Iterator<Item = f64>, count: usize, p: f64, metric: output::Metric) -> Option<f64> {
+    if count == 0 {
+        return None;
+    }
+    let rank = ((p / 100.0 * count as f64).ceil() as usize).clamp(1, count);
+    // ranks count up from the lowest score as shown, which is the most similar end for distances
+    let index = match metric {
+        output::Metric::Similarity => count - rank,
+        output::Metric::Distance => rank - 1,
+    };
+    top.map(|score| metric.convert(score)).nth(index)
+}
+
+/// A glob and the weight of the files it matches
+#[derive(Debug, Clone)]
+struct WeightRule {
+    pattern: glob::Pattern,
+    weight: f64,
+}
+
+impl FromStr for WeightRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // split on the last '=', since globs can't really contain one anyways
+        let (pattern, weight) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected GLOB=WEIGHT, got \"{}\"", s))?;
+        let pattern = glob::Pattern::new(pattern).map_err(|err| err.msg.to_string())?;
+        let weight = weight.parse().map_err(|_| format!("\"{}\" isn't a number", weight))?;
+        Ok(WeightRule { pattern, weight })
+    }
+}
+
+/// Whether a glob matches a file, by its full path or just its name
+fn glob_matches(pattern: &glob::Pattern, path: &Path) -> bool {
+    pattern.matches_path(path) || path.file_name().is_some_and(|name| pattern.matches_path(Path::new(name)))
+}
+
+/// Finds the weight of a file, the first matching rule wins
+fn file_weight(path: &Path, rules: &[WeightRule]) -> f64 {
+    rules.iter().find(|rule| glob_matches(&rule.pattern, path)).map_or(1.0, |rule| rule.weight)
+}
+
+/// Which encoding to read some files with, from --encoding-map
+#[derive(Debug, Clone)]
+struct EncodingMap(Vec<(glob::Pattern, &'static Encoding)>);
+
+fn read_encoding_map(path: PathBuf) -> Result<EncodingMap, String> {
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| format!("couldn't read {}: {}", path.to_string_lossy(), err))?;
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at = |problem: String| format!("{}:{}: {}", path.to_string_lossy(), number + 1, problem);
+        // the encoding is the last word, so globs can have spaces in them
+        let (pattern, label) = line
+            .rsplit_once(char::is_whitespace)
+            .ok_or_else(|| at(format!("expected GLOB ENCODING, got \"{}\"", line)))?;
+        let pattern = glob::Pattern::new(pattern.trim()).map_err(|err| at(err.msg.to_string()))?;
+        let encoding = Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| at(format!("unknown encoding \"{}\"", label)))?;
+        rules.push((pattern, encoding));
+    }
+    Ok(EncodingMap(rules))
+}
+
+impl EncodingMap {
+    fn for_path(&self, path: &Path) -> Option<&'static Encoding> {
+        self.0.iter().find(|(pattern, _)| glob_matches(pattern, path)).map(|&(_, encoding)| encoding)
+    }
+}
+
+/// What --formatter failing on a file does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FormatterOnError {
+    Skip,
+    Raw,
+    Fail,
+}
+
+impl FromStr for FormatterOnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(FormatterOnError::Skip),
+            "raw" => Ok(FormatterOnError::Raw),
+            "fail" => Ok(FormatterOnError::Fail),
+            _ => Err(format!("unknown action \"{}\", expected skip, raw or fail", s)),
+        }
+    }
+}
+
+/// Marks a load error as --formatter failing with --formatter-on-error fail, which stops the run
+#[derive(Debug)]
+struct FormatterFailed;
+
+impl std::fmt::Display for FormatterFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "--formatter failed on it")
+    }
+}
+
+/// A set of files to compare against, and what to call them
+#[derive(Debug, Clone)]
+struct Baseline {
+    label: String,
+    pattern: PathBuf,
+}
+
+impl FromStr for Baseline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((label, pattern)) if !label.is_empty() && !pattern.is_empty() => Ok(Baseline {
+                label: label.to_string(),
+                pattern: PathBuf::from(pattern),
+            }),
+            _ => Err(format!("expected LABEL:GLOB, got \"{}\"", s)),
+        }
+    }
+}
+
+/// Finds which submission a file belongs to, if grouping is enabled
+fn group_key<'a>(path: &'a Path, opts: &CliArgs) -> Option<Cow<'a, Path>> {
+    if let Some(pattern) = &opts.group_regex {
+        // files that don't match aren't in any group, so they're compared against everything
+        let path = path.to_string_lossy();
+        let captures = pattern.captures(&path)?;
+        let found = captures.get(1).or(captures.get(0))?;
+        Some(Cow::Owned(PathBuf::from(found.as_str())))
+    } else if opts.group_by_parent {
+        path.ancestors().nth(opts.group_depth).map(Cow::Borrowed)
+    } else {
+        None
+    }
+}
+
+/// Puts a pair in a consistent order, so a pair and its reverse are the same
+fn ordered_pair(x: &Path, y: &Path) -> (PathBuf, PathBuf) {
+    if x <= y {
+        (x.to_path_buf(), y.to_path_buf())
+    } else {
+        (y.to_path_buf(), x.to_path_buf())
+    }
+}
+
+/// Leaves out pairs past --max-pairs-per-file, if it was given
+fn capped<'a>(pairs: &[(&'a PathBuf, &'a PathBuf, f64)], max: Option<usize>) -> Vec<(&'a PathBuf, &'a PathBuf, f64)> {
+    let Some(max) = max else {
+        return pairs.to_vec();
+    };
+    let kept = analysis::cap_per_file(pairs, max);
+    if kept.len() < pairs.len() {
+        log::info!("Not showing {} pairs, their files are already in {} pairs.", pairs.len() - kept.len(), max);
+    }
+    kept
+}
+
+/// Reads a list of pairs, one `a,b` per line. Lines from the logfile work
+/// too, the score in front is ignored.
+fn read_pair_list(path: &Path) -> std::io::Result<HashSet<(PathBuf, PathBuf)>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut pairs = HashSet::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let (x, y) = match fields[..] {
+            [x, y] | [_, x, y] => (x, y),
+            _ => {
+                log::warn!("Don't know what to do with \"{}\" in {}, skipping it.", line, path.to_string_lossy());
+                continue;
+            }
+        };
+        // the same paths the files were loaded from, if they still exist
+        let canonical = |path: &str| std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        pairs.insert(ordered_pair(&canonical(x), &canonical(y)));
+    }
+    Ok(pairs)
+}
+
+/// Reads the scores from a CSV logfile, by pair in `ordered_pair` order
+fn read_logfile(path: &Path) -> std::io::Result<HashMap<(PathBuf, PathBuf), f64>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut scores = HashMap::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut fields = line.splitn(3, ',');
+        let (Some(Ok(score)), Some(x), Some(y)) = (fields.next().map(str::parse), fields.next(), fields.next())
+        else {
+            log::warn!("Don't know what to do with \"{}\" in {}, skipping it.", line, path.to_string_lossy());
+            continue;
+        };
+        scores.insert(ordered_pair(Path::new(x), Path::new(y)), score);
+    }
+    Ok(scores)
+}
+
+/// Reads the logfiles given to --merge and combines their scores, by pair of groups if there are any
+fn merge_logfiles(opts: &CliArgs) -> anyhow::Result<HashMap<(PathBuf, PathBuf), f64>> {
+    use anyhow::Context;
+    if opts.from_scores.is_some() {
+        anyhow::bail!("--merge and --from-scores can't be used together");
+    }
+    let mut runs = Vec::new();
+    for path in expand_globs(&opts.files) {
+        let scores = read_logfile(&path).with_context(|| format!("couldn't read {}", path.to_string_lossy()))?;
+        let mut run: HashMap<(PathBuf, PathBuf), f64> = HashMap::new();
+        for ((x, y), score) in scores {
+            let pair = match (group_key(&x, opts), group_key(&y, opts)) {
+                (Some(a), Some(b)) if a == b => continue,
+                (Some(a), Some(b)) => ordered_pair(&a, &b),
+                _ => (x, y),
+            };
+            // combined as similarities, so the max is the most similar either way
+            let score = opts.metric.convert(score);
+            let best = run.entry(pair).or_insert(score);
+            *best = best.max(score);
+        }
+        runs.push(run);
+    }
+    if runs.is_empty() {
+        anyhow::bail!("none of the logfiles were found");
+    }
+    let merged = analysis::combine_runs(&runs, opts.merge_by);
+    Ok(merged.into_iter().map(|(pair, score)| (pair, opts.metric.convert(score))).collect())
+}
+
+/// Finds the deepest directory that contains all of the paths
+fn common_ancestor<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> PathBuf {
+    let mut paths = paths.into_iter();
+    let Some(first) = paths.next() else {
+        return PathBuf::new();
+    };
+    let mut ancestor = first.parent().unwrap_or(first).to_path_buf();
+    for path in paths {
+        while !path.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                break;
+            }
+        }
+    }
+    ancestor
+}
+
+/// Writes `path` relative to `base`, going up with `..` where it has to
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let shared = path.components().zip(base.components()).take_while(|(a, b)| a == b).count();
+    // nothing in common, like a different drive on windows
+    if shared == 0 {
+        return path.to_path_buf();
+    }
+    let mut relative: PathBuf = base.components().skip(shared).map(|_| "..").collect();
+    relative.extend(path.components().skip(shared));
+    relative
+}
+
+/// Takes a list of paths and turns them into paths matching files, leaving out ignored ones
+fn filter_paths(globs: &Vec<PathBuf>, ignored: &[glob::Pattern]) -> Vec<PathBuf> {
+    without_ignored(only_files(expand_globs(globs)), ignored)
+}
+
+/// Drops files matching any of the patterns, by their full path or just their name
+fn without_ignored(files: Vec<PathBuf>, ignored: &[glob::Pattern]) -> Vec<PathBuf> {
+    if ignored.is_empty() {
+        return files;
+    }
+    let count = files.len();
+    let files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|path| !ignored.iter().any(|pattern| glob_matches(pattern, path)))
+        .collect();
+    log::debug!("Ignoring {} files.", count - files.len());
+    files
+}
+
+/// Finds the first glob that's invalid or matches nothing, for --strict-globs
+fn check_globs(globs: &[PathBuf]) -> Result<(), String> {
+    for pattern in globs {
+        let pattern = pattern.as_os_str().to_string_lossy();
+        match glob::glob(&pattern) {
+            Ok(mut paths) => {
+                if !paths.any(|path| path.is_ok()) {
+                    return Err(format!("\"{}\" didn't match any files.", pattern));
+                }
+            }
+            Err(err) => return Err(format!("\"{}\" is not a valid pattern. ({})", pattern, err.msg)),
+        }
+    }
+    Ok(())
+}
+
+/// Finds everything matching a list of globs
+fn expand_globs(globs: &Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    // what each real glob matched, to notice ones that mostly match the same files
+    let mut matched: Vec<(String, HashSet<PathBuf>)> = Vec::new();
+    for pattern in globs {
+        let pattern = pattern.as_os_str().to_string_lossy();
+        let paths = glob::glob(&pattern);
+        match paths {
+            Ok(paths) => {
+                let count = files.len();
+                files.extend(paths.filter_map(Result::ok));
+                if count == files.len() {
+                    log::warn!("\"{}\" didn't match any files.", &pattern);
+                }
+                // plain paths (like from the shell expanding a glob) only match themselves
+                if pattern.contains(['*', '?', '[']) {
+                    matched.push((pattern.to_string(), files[count..].iter().cloned().collect()));
+                }
+            }
+            Err(err) => {
+                log::warn!(
+                    "\"{}\" is not a valid pattern, and will be ignored. ({})",
+                    &pattern,
+                    &err.msg
+                );
+            }
+        }
+    }
+    for (i, (a, a_files)) in matched.iter().enumerate() {
+        for (b, b_files) in &matched[i + 1..] {
+            let shared = a_files.intersection(b_files).count();
+            let (smaller, smaller_files, other) =
+                if a_files.len() <= b_files.len() { (a, a_files, b) } else { (b, b_files, a) };
+            if shared > 0 && shared as f64 >= smaller_files.len() as f64 * GLOB_OVERLAP_RATIO {
+                log::debug!(
+                    "{} of the {} files \"{}\" matched were also matched by \"{}\", they're only compared once.",
+                    shared,
+                    smaller_files.len(),
+                    smaller,
+                    other
+                );
+            }
+        }
+    }
+    files
+}
+
+/// Reads a list of paths from stdin, one per line or separated by null bytes.
+///
+/// These are used as-is, not as globs.
+fn read_path_list(null_separated: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    Ok(input
+        .split(|&byte| byte == separator)
+        // stay lenient about windows line endings, unless we're being precise
+        .map(|path| match path {
+            [rest @ .., b'\r'] if !null_separated => rest,
+            path => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Canonicalizes paths, keeping only the regular files. Tar archives are
+/// swapped for the files in them.
+fn only_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    // globs like `submissions/*` match directories too, which we can't load
+    let mut directories = 0;
+    let files: Vec<PathBuf> = files
+        .iter()
+        .map(std::fs::canonicalize)
+        .filter_map(Result::ok)
+        .filter(|path| match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => true,
+            Ok(metadata) if metadata.is_dir() => {
+                log::debug!("Skipping {}, it's a directory.", path.to_string_lossy());
+                directories += 1;
+                false
+            }
+            Ok(_) => {
+                log::debug!("Skipping {}, it isn't a regular file.", path.to_string_lossy());
+                false
+            }
+            Err(err) => {
+                log::debug!("Skipping {}. ({})", path.to_string_lossy(), err);
+                false
+            }
+        })
+        .collect();
+    if directories > 0 {
+        log::warn!("Skipped {} directories matched by the given paths.", directories);
+    }
+    files
+        .into_iter()
+        .flat_map(|path| {
+            if !archive::is_tar(&path) {
+                return vec![path];
+            }
+            archive::files(&path).unwrap_or_else(|err| {
+                log::warn!("Couldn't read the archive {}, skipping it. ({:#})", path.to_string_lossy(), err);
+                Vec::new()
+            })
+        })
+        .collect()
+}
+
+/// Reads a file, trying again a few times if it fails in a way that might go away
+fn read_with_retries(path: &Path, retries: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::ErrorKind;
+    let mut attempt = 0;
+    loop {
+        let result = File::open(path).and_then(|mut file| {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).map(|_| bytes)
+        });
+        match result {
+            // things like network filesystems hiccuping, not missing files or permissions
+            Err(err)
+                if attempt < retries
+                    && matches!(
+                        err.kind(),
+                        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) =>
+            {
+                let backoff = std::time::Duration::from_millis(50 << attempt.min(10));
+                log::debug!(
+                    "Couldn't read {}, trying again in {:?}. ({})",
+                    path.to_string_lossy(),
+                    backoff,
+                    err
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Decompresses files ending in .gz (or .tgz), .bz2 or .xz, leaving anything else alone
+fn decompress(path: &Path, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context;
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let mut decompressed = Vec::new();
+    let result = match extension {
+        Some("gz" | "tgz") => flate2::read::MultiGzDecoder::new(&bytes[..])
+            .read_to_end(&mut decompressed)
+            .map(|_| ()),
+        Some("bz2") => bzip2::read::MultiBzDecoder::new(&bytes[..])
+            .read_to_end(&mut decompressed)
+            .map(|_| ()),
+        Some("xz") => lzma_rs::xz_decompress(&mut &bytes[..], &mut decompressed)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())),
+        _ => return Ok(bytes),
+    };
+    result.with_context(|| {
+        format!(
+            "couldn't decompress {} as {}",
+            path.to_string_lossy(),
+            extension.unwrap_or_default()
+        )
+    })?;
+    Ok(decompressed)
+}
+
+/// Loads a file to a string, handling non-utf-8 encoding.
+///
+/// Returns `None` for files that loaded fine but shouldn't be compared.
+fn load_file(path: &Path, program: &CliArgs, loading: &Loading) -> anyhow::Result<Option<String>> {
+    Ok(load_unnormalized(path, program)?.map(|text| normalize_text(path, text, program, loading)))
+}
+
+/// Everything `load_file` does before normalizing, for things that change the file first
+fn load_unnormalized(path: &Path, program: &CliArgs) -> anyhow::Result<Option<String>> {
+    let Some(text) = read_text(path, program)? else {
+        return Ok(None);
+    };
+    format_code(path, text, program)
+}
+
+/// Runs a file through --formatter, if there is one.
+///
+/// What happens to files it fails on is up to --formatter-on-error, `None` is for ones to skip.
+fn format_code(path: &Path, text: String, program: &CliArgs) -> anyhow::Result<Option<String>> {
+    let Some(formatter) = &program.formatter else {
+        return Ok(Some(text));
+    };
+    match run_filter(shell_command(formatter).env("CHEAT_CHECKER_FILE", path), &text) {
+        Ok(formatted) => {
+            log::debug!("Formatted {} with {}.", path.to_string_lossy(), formatter);
+            Ok(Some(formatted))
+        }
+        Err(err) => match program.formatter_on_error {
+            FormatterOnError::Raw => {
+                log::warn!(
+                    "Comparing {} unformatted, --formatter failed on it. ({:#})",
+                    path.to_string_lossy(),
+                    err
+                );
+                Ok(Some(text))
+            }
+            FormatterOnError::Skip => {
+                log::warn!("Skipping {}, --formatter failed on it. ({:#})", path.to_string_lossy(), err);
+                Ok(None)
+            }
+            FormatterOnError::Fail => Err(err.context(FormatterFailed)),
+        },
+    }
+}
+
+/// A file's bytes as they're stored, from inside an archive or --git-rev if it's there
+fn read_bytes(path: &Path, program: &CliArgs) -> anyhow::Result<Vec<u8>> {
+    Ok(match (archive::split(path), &program.git_rev) {
+        (Some((archive, inner)), _) => archive::read_file(archive, inner)?,
+        (None, Some(rev)) => git::read_at_revision(path, rev)?,
+        (None, None) => read_with_retries(path, program.read_retries)?,
+    })
+}
+
+/// Guesses what encoding a file is in
+fn detect_encoding(path: &Path, bytes: &[u8]) -> &'static Encoding {
+    let (detected, confidence, _) = chardet::detect(bytes);
+    let encoding = Encoding::for_label(detected.as_bytes()).unwrap_or_else(|| {
+        log::debug!(
+            "Don't know the encoding \"{}\" detected for {}, using UTF-8.",
+            detected,
+            path.to_string_lossy()
+        );
+        encoding_rs::UTF_8
+    });
+    log::debug!(
+        "Detected {} for {} ({:.0}% confident).",
+        detected,
+        path.to_string_lossy(),
+        confidence * 100.0
+    );
+    // anything that isn't utf-8 (or plain ascii) is worth knowing about
+    if encoding != encoding_rs::UTF_8 && !bytes.is_ascii() {
+        log::info!(
+            "Reading {} as {} ({:.0}% confident).",
+            path.to_string_lossy(),
+            encoding.name(),
+            confidence * 100.0
+        );
+    }
+    encoding
+}
+
+/// Reads and decodes a file, without normalizing it
+fn read_text(path: &Path, program: &CliArgs) -> anyhow::Result<Option<String>> {
+    let bytes = decompress(path, read_bytes(path, program)?)?;
+    let mapped = program.encoding_map.as_ref().and_then(|map| map.for_path(path));
+    let encoding = match mapped {
+        Some(encoding) => {
+            log::debug!("Reading {} as {}, from the encoding map.", path.to_string_lossy(), encoding.name());
+            encoding
+        }
+        None => {
+            let decodes = |encoding: &&'static Encoding| encoding.decode_without_bom_handling_and_without_replacement(&bytes).is_some();
+            match program.encodings.iter().copied().find(decodes) {
+                Some(encoding) => {
+                    log::debug!("Reading {} as {}, the first of --encodings that worked.", path.to_string_lossy(), encoding.name());
+                    encoding
+                }
+                None => {
+                    if !program.encodings.is_empty() {
+                        log::debug!("None of --encodings worked for {}, guessing.", path.to_string_lossy());
+                    }
+                    detect_encoding(path, &bytes)
+                }
+            }
+        }
+    };
+    let (decoded, _, had_errors) = encoding.decode(&bytes);
+    if had_errors && mapped.is_some() {
+        log::warn!("{} isn't valid {}, check the encoding map.", path.to_string_lossy(), encoding.name());
+    }
+    let mut loaded_file = decoded.into_owned();
+    // bytes that didn't decode became U+FFFD, which only adds noise to the score
+    if had_errors {
+        let replaced = loaded_file.chars().filter(|&c| c == char::REPLACEMENT_CHARACTER).count();
+        let ratio = replaced as f64 / loaded_file.chars().count().max(1) as f64;
+        log::debug!(
+            "{} characters of {} couldn't be decoded ({:.1}%).",
+            replaced,
+            path.to_string_lossy(),
+            ratio * 100.0
+        );
+        if program.max_replacement_ratio.is_some_and(|max| ratio > max) {
+            log::warn!(
+                "Skipping {}, {:.1}% of it couldn't be decoded as {}.",
+                path.to_string_lossy(),
+                ratio * 100.0,
+                encoding.name()
+            );
+            return Ok(None);
+        }
+        if program.strip_replacement_chars {
+            loaded_file.retain(|c| c != char::REPLACEMENT_CHARACTER);
+        }
+    }
+    if let Some(preprocess) = &program.preprocess {
+        match run_filter(shell_command(preprocess).env("CHEAT_CHECKER_FILE", path), &loaded_file) {
+            Ok(output) => loaded_file = output,
+            Err(err) => {
+                log::warn!("Skipping {}, --preprocess failed on it. ({:#})", path.to_string_lossy(), err);
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(loaded_file))
+}
+
+/// Runs a loaded file through every normalization that's turned on
+fn normalize_text(path: &Path, mut loaded_file: String, program: &CliArgs, loading: &Loading) -> String {
+    if let Some(language) = program.strip_imports {
+        loaded_file = normalize::strip_imports(language, &loaded_file);
+    }
+    if let Some(form) = program.unicode_normalize {
+        loaded_file = normalize::unicode_normalize(form, &loaded_file);
+    }
+    if program.fold_homoglyphs {
+        loaded_file = normalize::fold_homoglyphs(&loaded_file);
+    }
+    if let Some(profile) = profile::for_path(&loading.profiles, path) {
+        log::debug!("Using the {} profile for {}.", profile.name, path.to_string_lossy());
+        loaded_file = profile.apply(loaded_file, program.tab_width);
+    }
+    if program.skip_head > 0 || program.skip_tail > 0 {
+        loaded_file = normalize::skip_lines(&loaded_file, program.skip_head, program.skip_tail);
+    }
+    if let Some(language) = program.ast {
+        match ast::normalized_ast(language, &loaded_file) {
+            Ok(tree) => loaded_file = tree,
+            Err(err) => log::warn!(
+                "Couldn't parse {}, comparing it as text. ({})",
+                path.to_string_lossy(),
+                err
+            ),
+        }
+    }
+    if program.mask_numbers {
+        loaded_file = normalize::mask_numbers(&loaded_file);
+    }
+    if let Some(language) = program.mask_identifiers {
+        loaded_file = normalize::mask_identifiers(language, &loaded_file);
+    }
+    if program.keep_indent {
+        loaded_file = normalize::normalize_indentation(&loaded_file, program.tab_width);
+    }
+    // before --trim, which takes out the lines too
+    if let similarity::Algorithm::SortedBlocks(language) = program.algorithm {
+        loaded_file = split::sorted_blocks(language, &loaded_file);
+    }
+    if let Some(base) = &loading.base {
+        loaded_file = analysis::line_diff(base, &loaded_file)
+            .into_iter()
+            .filter(|(kind, _)| *kind == '+')
+            .map(|(_, line)| format!("{}\n", line))
+            .collect();
+    }
+    // filter out whitespace characters
+    if program.trim {
+        let before = loaded_file.chars().count();
+        loaded_file = loaded_file.chars()
+            .filter(|x| !x.is_whitespace()).collect();
+        let after = loaded_file.chars().count();
+        if after < MIN_TRIMMED_LENGTH && before >= MIN_TRIMMED_LENGTH {
+            log::warn!(
+                "--trim took {} from {} characters down to {}, so its scores won't mean much.",
+                path.to_string_lossy(),
+                before,
+                after
+            );
+        }
+    }
+    if let Some((start, end)) = program.compare_fraction {
+        loaded_file = normalize::keep_fraction(&loaded_file, start, end);
+    }
+    loaded_file
+}
+
+/// What loading files needs besides the options, worked out once before any of them are loaded
+struct Loading {
+    /// The ones the user picked, in the order they picked them
+    profiles: Vec<profile::Profile>,
+    /// The --base file, normalized the same way as everything else
+    base: Option<String>,
+}
+
+/// Runs a command in the shell, for --preprocess and --formatter
+fn shell_command(cmd: &str) -> Command {
+    let mut command = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    command.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd);
+    command
+}
+
+/// Pipes some text through a program, returning what it prints
+fn run_filter(command: &mut Command, input: &str) -> anyhow::Result<String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // write from another thread, otherwise we'd deadlock on anything
+    // that starts printing before it's done reading
+    let mut stdin = child.stdin.take().expect("stdin was piped");
+    let output = thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(input.as_bytes()));
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} ({})", output.status, stderr.lines().last().unwrap_or_default());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn main() {
+    // checked before parsing, so it works without a --sensitivity like --help does
+    if std::env::args().skip(1).any(|arg| arg == "--help-algorithms") {
+        print_algorithms();
+        return;
+    }
+    // --- Process arguments and file list
+    let mut opts = cli_args().run();
+    // autodetect parallelism if set to 0. the logger isn't set up yet, so
+    // hang on to why it failed until it is
+    let mut detection_error = None;
+    if opts.jobs == 0 {
+        opts.jobs = thread::available_parallelism()
+            .unwrap_or_else(|err| {
+                detection_error = Some(err);
+                NonZeroUsize::new(1).unwrap()
+            })
+            .into();
+    }
+    match &mut opts.algorithm {
+        similarity::Algorithm::Levenshtein(unit) | similarity::Algorithm::Damerau(unit) => *unit = opts.unit,
+        similarity::Algorithm::Lines(overlap) => *overlap = opts.overlap_metric,
+        similarity::Algorithm::Confidence(weights) => *weights = opts.confidence_weights.unwrap_or_default(),
+        similarity::Algorithm::Structure | similarity::Algorithm::TfIdf | similarity::Algorithm::Winnowing => (),
+        similarity::Algorithm::FuzzyShingles(shingling) => *shingling = opts.fuzzy_shingle.unwrap_or_default(),
+        similarity::Algorithm::SortedBlocks(language) => {
+            if let Some(chosen) = opts.block_language {
+                *language = chosen;
+            }
+        }
+    }
+    let opts = opts;
+    if opts.debug_info {
+        print_debug_info(&opts);
+        return;
+    }
+    if let Some(count) = opts.benchmark {
+        benchmark(count, &opts);
+        return;
+    }
+    // initialize logger based on chosen debug level
+    let level = if opts.verbose {
+        Debug
+    } else if opts.quiet {
+        Warn
+    } else {
+        Info
+    };
+    logging::init(opts.log_format, level);
+    if let Some(err) = detection_error {
+        log::warn!(
+            "Couldn't tell how many threads to use, so only using 1. Set --jobs or CHEAT_CHECKER_JOBS to use more. ({})",
+            err
+        );
+    }
+    if opts.unit != similarity::Unit::Chars
+        && !matches!(opts.algorithm, similarity::Algorithm::Levenshtein(_) | similarity::Algorithm::Damerau(_))
+    {
+        log::warn!("--unit only does anything with --algorithm levenshtein or damerau.");
+    }
+    if opts.overlap_metric != similarity::Overlap::Jaccard && !matches!(opts.algorithm, similarity::Algorithm::Lines(_)) {
+        log::warn!("--overlap-metric only does anything with --algorithm lines.");
+    }
+    if !opts.sensitivity_map.is_empty() && matches!(opts.threshold, Threshold::Percentile { .. }) {
+        log::warn!("--sensitivity-map only does anything with a fixed --sensitivity.");
+    }
+    if opts.confidence_weights.is_some() && !matches!(opts.algorithm, similarity::Algorithm::Confidence(_)) {
+        log::warn!("--confidence-weights only does anything with --algorithm confidence.");
+    }
+    if opts.block_language.is_some() && !matches!(opts.algorithm, similarity::Algorithm::SortedBlocks(_)) {
+        log::warn!("--block-language only does anything with --algorithm sorted-blocks.");
+    }
+    if opts.fuzzy_shingle.is_some() && !matches!(opts.algorithm, similarity::Algorithm::FuzzyShingles(_)) {
+        log::warn!("--fuzzy-shingle only does anything with --algorithm fuzzy-shingles.");
+    }
+    if opts.block_language.is_none() && matches!(opts.algorithm, similarity::Algorithm::SortedBlocks(_)) {
+        log::error!("--algorithm sorted-blocks needs --block-language, to know how to split the files.");
+        return;
+    }
+    if opts.trim && opts.keep_indent {
+        log::error!("--trim and --keep-indent can't be used together.");
+        return;
+    }
+    if opts.group_by_parent && opts.group_regex.is_some() {
+        log::error!("--group-by-parent and --group-regex can't be used together.");
+        return;
+    }
+    if opts.group_depth != 1 && !opts.group_by_parent {
+        log::warn!("--group-depth only does anything with --group-by-parent.");
+    }
+    let needs_contents = opts.show_lcs
+        || opts.show_ranges
+        || opts.granular.is_some()
+        || opts.algorithm == similarity::Algorithm::TfIdf
+        || opts.try_all_normalizations
+        || opts.entropy
+        || opts.min_pair_length > 0
+        || opts.exact_only
+        || opts.cluster_lsh
+        || opts.centroids
+        || opts.common_lines.is_some()
+        || opts.snippet.is_some()
+        || opts.snippet_file.is_some()
+        || opts.split_functions.is_some()
+        || opts.concat_by_dir
+        || opts.prepare_only.is_some()
+        || opts.json_details
+        || opts.verify_serial;
+    if opts.no_preload && needs_contents {
+        log::error!("--no-preload doesn't keep the files around, so it can't be used with options that need all of them.");
+        return;
+    }
+    if opts.dir_a.is_some() != opts.dir_b.is_some() {
+        log::error!("--dir-a and --dir-b need to be used together.");
+        return;
+    }
+    if opts.concat_by_dir && opts.dir_a.is_some() {
+        log::error!("--concat-by-dir and --dir-a can't be used together.");
+        return;
+    }
+    if opts.concat_by_dir && opts.split_functions.is_some() {
+        log::error!("--concat-by-dir and --split-functions can't be used together.");
+        return;
+    }
+    if opts.sqlite.is_some() && !cfg!(feature = "sqlite") {
+        log::error!("This was built without SQLite support, rebuild it with --features sqlite to use --sqlite.");
+        return;
+    }
+    if opts.profile_output.is_some() && !cfg!(feature = "profiling") {
+        log::error!("This was built without profiling, rebuild it with --features profiling to use --profile-output.");
+        return;
+    }
+    if opts.snippet.is_some() && opts.snippet_file.is_some() {
+        log::error!("--snippet and --snippet-file can't be used together.");
+        return;
+    }
+    if (opts.snippet.is_some() || opts.snippet_file.is_some()) && !matches!(opts.threshold, Threshold::Fixed { .. }) {
+        log::error!("--snippet needs a fixed --sensitivity.");
+        return;
+    }
+    if opts.knn.is_some() && opts.flag_files {
+        log::error!("--knn and --flag-files can't be used together.");
+        return;
+    }
+    if opts.granular.is_some() && opts.split_functions.is_some() {
+        log::error!("--granular and --split-functions can't be used together, --granular compares functions already.");
+        return;
+    }
+    if opts.dump_workqueue.is_some() && opts.split_functions.is_some() {
+        log::error!("--dump-workqueue can't be used with --split-functions, the functions aren't files to list.");
+        return;
+    }
+    if opts.aggregate_by_file && (opts.knn.is_some() || opts.flag_files) {
+        log::error!("--aggregate-by-file can't be used with --knn or --flag-files.");
+        return;
+    }
+    if opts.strict_globs {
+        if let Err(problem) = check_globs(&opts.files).and_then(|()| check_globs(&opts.against)) {
+            log::error!("{}", problem);
+            std::process::exit(2);
+        }
+    }
+    // profiles the user picked, in the order they picked them
+    let profiles = match profile::available(opts.profiles.as_deref()) {
+        Ok(available) => {
+            let mut chosen = Vec::new();
+            for name in &opts.profile {
+                match available.iter().find(|profile| &profile.name == name) {
+                    Some(profile) => chosen.push(profile.clone()),
+                    None => {
+                        let names: Vec<_> = available.iter().map(|profile| profile.name.as_str()).collect();
+                        log::error!("There's no profile named \"{}\", expected one of {}.", name, names.join(", "));
+                        return;
+                    }
+                }
+            }
+            chosen
+        }
+        Err(err) => {
+            log::error!("Couldn't load the profiles. ({:#})", err);
+            return;
+        }
+    };
+    let mut loading = Loading { profiles, base: None };
+    if let Some(base) = &opts.base {
+        // there's no base yet, so this doesn't get diffed against itself
+        match load_file(base, &opts, &loading) {
+            Ok(Some(text)) => loading.base = Some(text),
+            Ok(None) => {
+                log::error!("{} was skipped, so it can't be the --base.", base.to_string_lossy());
+                return;
+            }
+            Err(err) => {
+                log::error!("Couldn't load the base {}. ({:#})", base.to_string_lossy(), err);
+                return;
+            }
+        }
+    }
+    if let Some(new) = &opts.add {
+        let Threshold::Fixed { sensitivity } = opts.threshold else {
+            log::error!("--add needs a fixed --sensitivity, since only one file's pairs are scored.");
+            return;
+        };
+        let against = filter_paths(&opts.against, &opts.ignore_file);
+        let sensitivity = opts.metric.convert(sensitivity);
+        if let Err(err) = add::add(new, &against, opts.store.as_deref(), &opts, &loading, sensitivity) {
+            log::error!("Couldn't add {}. ({:#})", new.to_string_lossy(), err);
+        }
+        return;
+    }
+    if opts.json_details && opts.serve.is_none() {
+        log::warn!("--json-details only changes the JSON from --serve.");
+    }
+    if opts.worst && (opts.logfile.is_some() || opts.flagged_log.is_some()) {
+        log::warn!("--worst doesn't write logfiles, ignoring --log and --flagged-log.");
+    }
+    if opts.follow && opts.watch.is_none() {
+        log::error!("--follow only works with --watch.");
+        return;
+    }
+    if let Some(dir) = &opts.watch {
+        let Threshold::Fixed { sensitivity } = opts.threshold else {
+            log::error!("--watch needs a fixed --sensitivity, since there's never a full set of scores to take a percentile of.");
+            return;
+        };
+        let initial = filter_paths(&opts.files, &opts.ignore_file);
+        if let Err(err) = watch::watch(dir, &initial, &opts, &loading, opts.metric.convert(sensitivity)) {
+            log::error!("Couldn't watch {}. ({:#})", dir.to_string_lossy(), err);
+        }
+        return;
+    }
+    if let Some(path) = &opts.self_check {
+        if let Err(err) = self_check(path, &opts, &loading) {
+            log::error!("Couldn't check {}. ({:#})", path.to_string_lossy(), err);
+        }
+        return;
+    }
+    if let Some((a, b)) = &opts.explain {
+        if let Err(err) = explain(a, b, &opts, &loading) {
+            log::error!("Couldn't explain {} and {}. ({:#})", a.to_string_lossy(), b.to_string_lossy(), err);
+        }
+        return;
+    }
+    let mut timings = Timings::default();
+    // scores from an earlier run, with the files they're for
+    let imported = match &opts.from_scores {
+        _ if opts.merge => match merge_logfiles(&opts) {
+            Ok(scores) => {
+                log::info!("Merged {} scores from {} logfiles.", scores.len(), opts.files.len());
+                Some(scores)
+            }
+            Err(err) => {
+                log::error!("Couldn't merge the logfiles. ({:#})", err);
+                return;
+            }
+        },
+        Some(path) => match read_logfile(path) {
+            Ok(scores) => {
+                log::info!("Read {} scores from {}.", scores.len(), path.to_string_lossy());
+                Some(scores)
+            }
+            Err(err) => {
+                log::error!("Couldn't read the scores from {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        },
+        None => None,
+    };
+    if imported.is_some() && (opts.show_lcs || opts.show_ranges || opts.try_all_normalizations || opts.verify_serial) {
+        log::error!("--from-scores doesn't read the files, so it can't be used with options that need them.");
+        return;
+    }
+    let mut paths = match &imported {
+        Some(scores) => {
+            let named: HashSet<&PathBuf> = scores.keys().flat_map(|(x, y)| [x, y]).collect();
+            named.into_iter().cloned().collect()
+        }
+        None => expand_globs(&opts.files),
+    };
+    if opts.stdin_list {
+        match read_path_list(opts.null) {
+            Ok(list) => paths.extend(list),
+            Err(err) => log::error!("Couldn't read the list of files from stdin. ({})", err),
+        }
+    }
+    // just these pairs, when there's a list
+    let listed_pairs = match &opts.compare_pairs {
+        Some(path) => match read_pair_list(path) {
+            Ok(pairs) => {
+                let (found, missing): (HashSet<_>, HashSet<_>) =
+                    pairs.into_iter().partition(|(x, y)| x.is_file() && y.is_file());
+                let missing: HashSet<&PathBuf> =
+                    missing.iter().flat_map(|(x, y)| [x, y]).filter(|path| !path.is_file()).collect();
+                for file in missing {
+                    log::warn!("{} from the list of pairs doesn't exist, skipping its pairs.", file.to_string_lossy());
+                }
+                log::info!("Comparing {} listed pairs.", found.len());
+                paths.extend(found.iter().flat_map(|(x, y)| [x.clone(), y.clone()]));
+                Some(found)
+            }
+            Err(err) => {
+                log::error!("Couldn't read the pairs to compare from {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        },
+        None => None,
+    };
+    timings.finish("Globbing");
+    // imported files might not be around anymore, and don't need to be
+    let mut paths = match imported {
+        Some(_) => without_ignored(paths, &opts.ignore_file),
+        None => without_ignored(only_files(paths), &opts.ignore_file),
+    };
+    if let Some(base) = opts.base.as_ref().and_then(|base| base.canonicalize().ok()) {
+        paths.retain(|path| *path != base);
+    }
+    timings.finish("Canonicalizing");
+    if let Some(sample) = opts.file_sample.filter(|&sample| sample < paths.len()) {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+        log::warn!("Only comparing a sample of {} out of {} files.", sample, paths.len());
+        // sorted first, so the same seed picks the same files however they were listed
+        paths.sort();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(opts.seed);
+        paths.shuffle(&mut rng);
+        paths.truncate(sample);
+    }
+    // files to compare against, tagged with which baseline they're from
+    let mut baseline_labels: HashMap<PathBuf, String> = HashMap::new();
+    for baseline in &opts.baseline {
+        let found = filter_paths(&vec![baseline.pattern.clone()], &opts.ignore_file);
+        log::info!("Got {} files from baseline \"{}\".", found.len(), baseline.label);
+        for path in found {
+            baseline_labels.entry(path).or_insert_with(|| baseline.label.clone());
+        }
+    }
+    // which of --dir-a and --dir-b each file is from
+    let mut sides: HashMap<PathBuf, char> = HashMap::new();
+    if let (Some(a), Some(b)) = (&opts.dir_a, &opts.dir_b) {
+        for (side, dir) in [('A', a), ('B', b)] {
+            let found = filter_paths(&vec![dir.join("**").join("*")], &opts.ignore_file);
+            log::info!("Got {} files for side {} from {}.", found.len(), side, dir.to_string_lossy());
+            for path in found {
+                sides.entry(path).or_insert(side);
+            }
+        }
+    }
+    let paths: Vec<PathBuf> = paths
+        .into_iter()
+        .chain(baseline_labels.keys().cloned())
+        .chain(sides.keys().cloned())
+        .collect();
+    if !opts.baseline.is_empty() {
+        timings.finish("Finding baselines");
+    }
+    if let Some(expected) = opts.expect_files.filter(|&expected| expected != paths.len()) {
+        log::error!("Expected {} files to compare, but got {}.", expected, paths.len());
+        std::process::exit(2);
+    }
+    // make sure we have enough files
+    let snippet_mode = opts.snippet.is_some() || opts.snippet_file.is_some();
+    if paths.len() <= (!snippet_mode) as usize && opts.prepare_only.is_none() {
+        log::error!("Got {} files to compare, need at least 2.", paths.len());
+        return;
+    } else {
+        log::info!("Got {} files to compare.", paths.len())
+    }
+    // files from the same student usually share a directory, and comparing them is just noise
+    if opts.verbose && !opts.group_by_parent && opts.group_regex.is_none() {
+        let mut parents: HashMap<&Path, usize> = HashMap::new();
+        for path in &paths {
+            *parents.entry(path.parent().unwrap_or(path)).or_default() += 1;
+        }
+        let shared = parents.values().filter(|&&count| count > 1).sum::<usize>();
+        if parents.len() > 1 && shared as f64 >= paths.len() as f64 * SHARED_PARENT_RATIO {
+            log::debug!(
+                "{} of {} files share a directory with another file. If each directory is one \
+                 submission, --group-by-parent would make {} groups and skip comparing within them.",
+                shared,
+                paths.len(),
+                parents.len()
+            );
+        }
+    }
+    // pairs someone already looked at and decided were fine
+    let ignored_pairs = match &opts.ignore_pairs {
+        Some(path) => match read_pair_list(path) {
+            Ok(pairs) => {
+                log::info!("Ignoring {} reviewed pairs.", pairs.len());
+                pairs
+            }
+            Err(err) => {
+                log::error!("Couldn't read the pairs to ignore from {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        },
+        None => HashSet::new(),
+    };
+    let previous_run = match &opts.diff_against {
+        Some(path) => match read_logfile(path) {
+            Ok(scores) => Some(scores),
+            Err(err) => {
+                log::error!("Couldn't read the old logfile {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        },
+        None => None,
+    };
+    // a logfile that can't be made isn't worth losing the whole run over
+    let create_log = |path: &PathBuf| match File::create(path) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            log::warn!("Couldn't create {}, carrying on without it. ({})", path.to_string_lossy(), err);
+            None
+        }
+    };
+    // not even created for --worst, an empty file isn't a valid logfile in every format
+    let mut logfile: Option<File> = opts.logfile.as_ref().filter(|_| !opts.worst).and_then(create_log);
+    let mut flagged_log: Option<File> = opts.flagged_log.as_ref().filter(|_| !opts.worst).and_then(create_log);
+
+    // --- Compare files
+    // preload all files into memory
+    let mut files: HashMap<PathBuf, String> = HashMap::with_capacity(paths.len());
+    // which file each function came from, when splitting
+    let mut unit_sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+    // the files before normalizing, for --show-ranges
+    let mut originals: HashMap<PathBuf, String> = HashMap::new();
+    // directories are joined in order of their file names
+    let mut paths = paths;
+    if opts.concat_by_dir {
+        paths.sort();
+        paths.dedup();
+    }
+    // reading and decoding can take a while, so show that something's happening
+    let loading_bar = if opts.no_progress {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(paths.len() as u64).with_style(
+            ProgressStyle::with_template("Loading files {wide_bar} {pos}/{len}")
+                .expect("progress bar template is valid"),
+        )
+    };
+    // the scores are already known or files get loaded later, only the names matter
+    let loaded = if imported.is_some() || opts.no_preload {
+        paths.iter().map(|_| Ok(Some((None, String::new())))).collect()
+    } else {
+        preload(&paths, &opts, &loading, || loading_bar.inc(1))
+    };
+    let (mut unloadable, mut skipped) = (0, 0);
+    for (path, loaded) in paths.iter().zip(loaded) {
+        let (original, contents) = match loaded {
+            Ok(Some(loaded)) => loaded,
+            Ok(None) => {
+                skipped += 1;
+                continue;
+            }
+            Err(err) if err.is::<FormatterFailed>() => {
+                log::error!("Couldn't format {}. ({:#})", path.to_string_lossy(), err);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                log::warn!("Couldn't load {}, leaving it out. ({:#})", path.to_string_lossy(), err);
+                unloadable += 1;
+                continue;
+            }
+        };
+        if let Some(original) = original {
+            originals.insert(path.clone(), original);
+        }
+        let label = baseline_labels.get(path).cloned();
+        if let Some(language) = opts.split_functions {
+            let functions = split::functions(language, &contents);
+            if functions.is_empty() {
+                log::debug!("Didn't find any functions in {}.", path.to_string_lossy());
+            }
+            // each function gets a made up path, so it can be shown like a file
+            for (name, text) in functions {
+                let key = PathBuf::from(format!("{}::{}", path.to_string_lossy(), name));
+                unit_sources.insert(key.clone(), path.clone());
+                if let Some(label) = &label {
+                    baseline_labels.insert(key.clone(), label.clone());
+                }
+                files.insert(key, text);
+            }
+            continue;
+        }
+        let key = if opts.concat_by_dir {
+            let dir = path.parent().unwrap_or(path).to_path_buf();
+            let document = files.entry(dir.clone()).or_default();
+            if !document.is_empty() {
+                document.push('\n');
+            }
+            document.push_str(&contents);
+            dir
+        } else {
+            files.insert(path.clone(), contents);
+            path.clone()
+        };
+        if let Some(label) = label {
+            baseline_labels.insert(key.clone(), label);
+        }
+    }
+    loading_bar.finish_and_clear();
+    if unloadable + skipped > 0 {
+        log::info!("Comparing the other {} of {} files.", paths.len() - unloadable - skipped, paths.len());
+    }
+    let entropies: HashMap<PathBuf, f64> = if opts.entropy {
+        files.iter().map(|(path, text)| (path.clone(), analysis::entropy(text))).collect()
+    } else {
+        HashMap::new()
+    };
+    timings.finish("Loading");
+    if opts.concat_by_dir {
+        if files.len() <= 1 {
+            log::error!("Got {} directories to compare, need at least 2.", files.len());
+            return;
+        }
+        log::info!("Comparing {} directories.", files.len());
+    }
+    if opts.split_functions.is_some() {
+        log::info!("Comparing {} functions.", files.len());
+    }
+    if let Some(dir) = &opts.prepare_only {
+        let root = common_ancestor(files.keys());
+        for (path, text) in &files {
+            let target = dir.join(path.strip_prefix(&root).unwrap_or(path));
+            let written = target
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&target, text));
+            if let Err(err) = written {
+                log::error!("Couldn't write {}. ({})", target.to_string_lossy(), err);
+                return;
+            }
+        }
+        log::info!("Wrote {} prepared files to {}.", files.len(), dir.to_string_lossy());
+        return;
+    }
+
+    // results go to stdout unless told otherwise, logs and progress stay on stderr
+    let mut out: Box<dyn Write> = match &opts.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => {
+                log::error!("Couldn't create {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        },
+        None => Box::new(std::io::stdout()),
+    };
+
+    if let Some(min_files) = opts.common_lines {
+        let common = analysis::common_lines(&files, min_files);
+        log::info!("Found {} lines in more than {} files.", common.len(), min_files);
+        for (line, paths) in &common {
+            let _ = writeln!(out, "{} files:\t{}", paths.len(), line);
+            for path in paths {
+                let _ = writeln!(out, "\t{}", path.to_string_lossy());
+            }
+        }
+    }
+
+    if snippet_mode {
+        let snippet = match (&opts.snippet, &opts.snippet_file) {
+            (Some(text), _) => normalize_text(Path::new("snippet"), text.clone(), &opts, &loading),
+            (None, Some(path)) => match load_file(path, &opts, &loading) {
+                Ok(Some(text)) => text,
+                Ok(None) => return,
+                Err(err) => {
+                    log::error!("Couldn't read the snippet from {}. ({:#})", path.to_string_lossy(), err);
+                    return;
+                }
+            },
+            (None, None) => unreachable!("only in snippet mode"),
+        };
+        // how much of the snippet is in each file, whatever --algorithm says
+        let algorithm = similarity::Algorithm::Lines(similarity::Overlap::Containment);
+        let scorer = algorithm.scorer();
+        let mut prepared: HashMap<PathBuf, similarity::PreparedFile> =
+            files.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+        // made up, so it can't be the same as one of the files
+        let key = PathBuf::from("\0snippet");
+        prepared.insert(key.clone(), scorer.prepare(&snippet));
+        let workqueue: Vec<(&PathBuf, &PathBuf)> = files.keys().map(|path| (&key, path)).collect();
+        let Threshold::Fixed { sensitivity } = opts.threshold else {
+            unreachable!("checked before loading");
+        };
+        let sensitivity = opts.metric.convert(sensitivity);
+        let mut found = Vec::new();
+        let snippet_lines = prepared[&key].fingerprints.len().max(1);
+        let comparing = Comparing::new(algorithm, None);
+        compare_all(workqueue, &prepared, &comparing, opts.jobs, &AtomicBool::new(false), |_, path, score| {
+            // containment is out of the smaller one, but a file smaller than the
+            // snippet can't contain all of it
+            let file_lines = prepared[path].fingerprints.len();
+            let score = score * file_lines.min(snippet_lines) as f64 / snippet_lines as f64;
+            if score >= sensitivity {
+                found.push((path, score));
+            }
+        });
+        found.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).expect("Couldn't compare two scores").then_with(|| a.0.cmp(b.0)));
+        log::info!("{} files contain the snippet.", found.len());
+        for (path, score) in found {
+            let _ = writeln!(out, "{:.6}\t{}", opts.metric.convert(score), path.to_string_lossy());
+        }
+        let _ = out.flush();
+        return;
+    }
+
+    if opts.exact_only {
+        let groups = analysis::duplicate_groups(&files);
+        log::info!("Found {} groups of identical files.", groups.len());
+        for group in groups {
+            let _ = writeln!(out, "{} identical files:", group.len());
+            for path in group {
+                let _ = writeln!(out, "\t{}", path.to_string_lossy());
+            }
+        }
+        let _ = out.flush();
+        return;
+    }
+
+    if opts.cluster_lsh {
+        let clusters = cluster::clusters(&files);
+        log::info!("Found {} clusters of similar files.", clusters.len());
+        for (members, similarity) in clusters {
+            let _ = writeln!(out, "{} files, about {:.2} similar:", members.len(), opts.metric.convert(similarity));
+            for path in members {
+                let _ = writeln!(out, "\t{}", path.to_string_lossy());
+            }
+        }
+        let _ = out.flush();
+        return;
+    }
+
+    if opts.centroids {
+        let lines = similarity::Algorithm::Lines(similarity::Overlap::Containment).scorer();
+        let mut grouped: Vec<(&PathBuf, Cow<Path>, Vec<u64>)> = files
+            .iter()
+            .filter_map(|(path, text)| Some((path, group_key(path, &opts)?, lines.prepare(text).fingerprints)))
+            .collect();
+        grouped.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        if grouped.len() < files.len() {
+            log::warn!("{} files aren't in any group, leaving them out.", files.len() - grouped.len());
+        }
+        for (path, group, score) in analysis::centroid_matches(&grouped) {
+            let _ = writeln!(
+                out,
+                "{:.6}\t{}\t{}",
+                opts.metric.convert(score),
+                path.to_string_lossy(),
+                group.to_string_lossy()
+            );
+        }
+        let _ = out.flush();
+        return;
+    }
+
+    // other ways of normalizing each file, when trying them all
+    let variant_texts: HashMap<&PathBuf, Vec<String>> = if opts.try_all_normalizations {
+        files.iter().map(|(path, text)| (path, normalize::variants(path, text))).collect()
+    } else {
+        HashMap::new()
+    };
+
+    // the functions and runs of lines in each file, for --granular
+    let part_texts: HashMap<&PathBuf, Vec<(similarity::Granularity, String)>> = match opts.granular {
+        Some(language) => files.iter().map(|(path, text)| (path, granular_parts(language, text))).collect(),
+        None => HashMap::new(),
+    };
+
+    // anything the algorithm needs to know about each file, worked out once
+    let prepared: HashMap<PathBuf, similarity::PreparedFile> = {
+        let scorer = opts.algorithm.scorer();
+        files
+            .iter()
+            .map(|(path, text)| {
+                let mut file = scorer.prepare(text);
+                if let Some(variants) = variant_texts.get(path) {
+                    file.variants = variants.iter().map(|text| scorer.prepare(text)).collect();
+                }
+                if let Some(parts) = part_texts.get(path) {
+                    file.parts = parts.iter().map(|(kind, text)| (*kind, scorer.prepare(text))).collect();
+                }
+                (path.clone(), file)
+            })
+            .collect()
+    };
+    // how common each shingle is, for every scorer that scores these files
+    let rarity = similarity::count_rarity(opts.algorithm, prepared.values());
+
+    // weight of each file, only shown if the user gave any rules
+    let weights: HashMap<&PathBuf, f64> = files
+        .keys()
+        .map(|path| (path, file_weight(path, &opts.weight)))
+        .collect();
+
+    // which student each file is from, if grouping
+    let groups: HashMap<&PathBuf, Cow<Path>> = files
+        .keys()
+        .filter_map(|path| group_key(path, &opts).map(|group| (path, group)))
+        .collect();
+
+    // queue of comparisons that need to be made
+    // in a fixed order, hashmaps iterate differently every run and --sample
+    // would pick different pairs even with the same --seed.
+    // anything else that lists every file goes by this order too
+    let mut sorted_paths: Vec<&PathBuf> = files.keys().collect();
+    sorted_paths.sort();
+    // functions are on the side of the file they're from
+    let side_of = |path: &PathBuf| sides.get(path).or_else(|| sides.get(unit_sources.get(path)?)).copied();
+    // files matching --query-glob, functions going by the file they're from
+    let queries: Option<HashSet<&PathBuf>> = opts.query_glob.as_ref().map(|pattern| {
+        let queries: HashSet<&PathBuf> = sorted_paths
+            .iter()
+            .copied()
+            .filter(|&path| glob_matches(pattern, unit_sources.get(path).unwrap_or(path)))
+            .collect();
+        log::info!("{} of the files match --query-glob.", queries.len());
+        queries
+    });
+    let mut workqueue: Vec<(&PathBuf, &PathBuf)> = Vec::new();
+    // so it's easy to check the grouping actually did something
+    let mut same_group = 0;
+    if let Some(listed) = &listed_pairs {
+        // exactly what was asked for, ones whose files got skipped while loading can't be
+        workqueue = listed
+            .iter()
+            .filter_map(|(x, y)| Some((files.get_key_value(x)?.0, files.get_key_value(y)?.0)))
+            .collect();
+        workqueue.sort();
+    } else {
+        for &x in &sorted_paths {
+            for &y in &sorted_paths {
+                // skip this comparison if we've already compared the two in opposite direction
+                // or if it's the same file twice
+                if x >= y {
+                    continue;
+                }
+                // or if they're both from baselines
+                if baseline_labels.contains_key(x) && baseline_labels.contains_key(y) {
+                    continue;
+                }
+                // or if they're on the same side
+                if let (Some(a), Some(b)) = (side_of(x), side_of(y)) {
+                    if a == b {
+                        continue;
+                    }
+                }
+                // or if they're both queries, or neither is
+                if let Some(queries) = &queries {
+                    if queries.contains(x) == queries.contains(y) {
+                        continue;
+                    }
+                }
+                // or if they're both functions from the same file
+                if let (Some(a), Some(b)) = (unit_sources.get(x), unit_sources.get(y)) {
+                    if a == b {
+                        continue;
+                    }
+                }
+                // or if they're next to each other, functions going by the file they're from
+                if opts.skip_same_dir && unit_sources.get(x).unwrap_or(x).parent() == unit_sources.get(y).unwrap_or(y).parent() {
+                    continue;
+                }
+                // or if they're different files, when we only want the same one from everyone
+                if opts.same_basename && x.file_name() != y.file_name() {
+                    continue;
+                }
+                // or if they're both from the same student
+                if let (Some(a), Some(b)) = (groups.get(x), groups.get(y)) {
+                    if a == b {
+                        same_group += 1;
+                        continue;
+                    }
+                }
+                workqueue.push((x, y));
+            }
+        }
+        if !groups.is_empty() {
+            let count = groups.values().collect::<HashSet<_>>().len();
+            log::info!("Skipping {} pairs of files in the same group, out of {} groups.", same_group, count);
+        }
+    }
+    if let Some(path) = &opts.resume {
+        match read_pair_list(path) {
+            Ok(done) => {
+                let before = workqueue.len();
+                workqueue.retain(|(x, y)| !done.contains(&ordered_pair(x, y)));
+                log::info!("Resuming, {} of {} pairs were already compared.", before - workqueue.len(), before);
+            }
+            Err(err) => {
+                log::error!("Couldn't read the logfile to resume from {}. ({})", path.to_string_lossy(), err);
+                return;
+            }
+        }
+    }
+    if let Some(sample) = opts.sample.filter(|&sample| sample < workqueue.len()) {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+        log::warn!(
+            "Only comparing a sample of {} out of {} pairs, so some similar pairs will be missed.",
+            sample,
+            workqueue.len()
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(opts.seed);
+        workqueue.shuffle(&mut rng);
+        workqueue.truncate(sample);
+    }
+    if let Some(path) = &opts.dump_workqueue {
+        // sampling shuffles it
+        workqueue.sort();
+        let written = File::create(path).map(BufWriter::new).and_then(|mut file| {
+            for (x, y) in &workqueue {
+                writeln!(file, "{},{}", x.to_string_lossy(), y.to_string_lossy())?;
+            }
+            file.flush()
+        });
+        match written {
+            Ok(()) => log::info!("Wrote {} pairs to {}.", workqueue.len(), path.to_string_lossy()),
+            Err(err) => log::error!("Couldn't write the pairs to {}. ({})", path.to_string_lossy(), err),
+        }
+        return;
+    }
+    // every score is kept when something needs all of them. otherwise only the
+    // ones that could make it past a percentile are, so huge runs still fit in memory
+    let keep_scores = logfile.is_some()
+        || flagged_log.is_some()
+        || opts.sqlite.is_some()
+        || opts.heatmap.is_some()
+        || opts.flag_files
+        || previous_run.is_some()
+        || opts.group_summary
+        || opts.relative_scores
+        || opts.verify_serial
+        || opts.worst
+        || opts.originality.is_some();
+    // hashmap for storing scores, we know exactly how many there'll be
+    let mut scores: HashMap<(PathBuf, PathBuf), f64> =
+        HashMap::with_capacity(if keep_scores { workqueue.len() } else { 0 });
+
+    // hand out the most expensive comparisons first, so that worker threads
+    // aren't left waiting on one big pair at the end of the run.
+    // workers pop() from the back, so that's where the expensive ones go.
+    // imported scores aren't compared at all, so there's nothing to order then
+    if imported.is_none() {
+        // --no-preload hasn't loaded anything yet, so it goes by how big the files are on disk
+        let sizes: HashMap<&PathBuf, usize> = if opts.no_preload {
+            files.keys().map(|path| (path, std::fs::metadata(path).map_or(0, |meta| meta.len() as usize))).collect()
+        } else {
+            files.iter().map(|(path, text)| (path, text.len())).collect()
+        };
+        workqueue.sort_by_cached_key(|(x, y)| sizes[*x].saturating_mul(sizes[*y]));
+    }
 
-    /// Logs all comparisons to this file.
-    #[bpaf(short, long("log"), argument("FILE"))]
-    logfile: Option<PathBuf>,
+    // on ctrl-c, stop handing out work and report whatever we've got so far.
+    // a second ctrl-c means they really want out.
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Err(err) = ctrlc::set_handler({
+        let stop = stop.clone();
+        move || {
+            if stop.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+        }
+    }) {
+        log::warn!("Couldn't set the ctrl-c handler, interrupting will lose all results. ({})", err);
+    }
+    let out_of_time = Arc::new(AtomicBool::new(false));
+    if let Some(budget) = opts.time_budget {
+        let stop = stop.clone();
+        let out_of_time = out_of_time.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs_f64(budget));
+            out_of_time.store(true, Ordering::Relaxed);
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
 
-    /// Program used to format code before checking
-    ///
-    /// Before comparing two files, we'll run them both through this program.
-    /// Improves detection, since changing the format won't affect the results
-    /// anymore.
-    ///
-    /// TODO
-    #[bpaf(short, long, argument("PROGRAM"), hide)]
-    _formatter: Option<String>,
+    // paths are shown relative to this in ci output and reports
+    let root = common_ancestor(files.keys());
+    let cwd = std::env::current_dir().unwrap_or_default();
+    // each output has its own way of showing paths, unless --paths picked one
+    let display = |path: &Path, default: output::PathStyle| -> String {
+        match opts.paths.unwrap_or(default) {
+            output::PathStyle::Cwd => relative_to(path, &cwd).to_string_lossy().into_owned(),
+            output::PathStyle::Absolute => path.to_string_lossy().into_owned(),
+            output::PathStyle::Common => path.strip_prefix(&root).unwrap_or(path).to_string_lossy().into_owned(),
+        }
+    };
+    let relative = |path: &Path| display(path, output::PathStyle::Common);
+    let absolute = |path: &Path| display(path, output::PathStyle::Absolute);
+    // find the widest name for printing later
+    let widest_name = files.keys().map(|path| absolute(path).len()).max().unwrap_or(0);
+    let mut flagged: Vec<(&PathBuf, &PathBuf, f64)> = Vec::new();
 
-    /// Remove whitespace before calculating similarity score
-    #[bpaf(short, long)]
-    trim: bool,
+    let metric = opts.metric;
+    // the median score and how spread out they are, once everything's compared
+    let spread: Cell<Option<(f64, f64)>> = Cell::new(None);
+    // https://no-color.org, any value but an empty one turns colors off
+    let colored = opts.output.is_none() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    let write_pair = |out: &mut dyn Write, x: &PathBuf, y: &PathBuf, score: f64| {
+        let baseline_label = baseline_labels.get(x).or(baseline_labels.get(y)).map(String::as_str);
+        // groups are shown by their name, like the student's folder
+        let group_name = |path: &PathBuf| {
+            groups
+                .get(path)
+                .and_then(|group| group.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let line = if let Some(template) = &opts.template_str {
+            template.render(&output::PairFields {
+                score,
+                a: &absolute(x),
+                b: &absolute(y),
+                group_a: &group_name(x),
+                group_b: &group_name(y),
+                weight: weights[x].max(weights[y]),
+                baseline: baseline_label.unwrap_or_default(),
+            })
+        } else if opts.ci {
+            format!("{:.6}\t{}\t{}", metric.convert(score), relative(x), relative(y))
+        } else if opts.output_style == output::OutputStyle::Grouped {
+            format!(
+                "{}\t{}\t{}\t{}\t{:.6}",
+                group_name(x),
+                relative(x),
+                group_name(y),
+                relative(y),
+                metric.convert(score)
+            )
+        } else {
+            // keep this import scoped small, otherwise everything gets
+            // a billion color methods in rust-analyzer.
+            use owo_colors::OwoColorize;
+            // todo unique color per file?
+            // formatted as 12.45678 (decimal place is 3) so 8 characters total, 5 after decimal thus 08.5
+            // colored by similarity either way
+            let shown = metric.convert(score);
+            let unusual = spread.get().map(|(median, deviation)| (score - median) / deviation);
+            let score = if colored {
+                let color = get_color(0.3, score, 1.0, opts.color_threshold_low, opts.color_threshold_high);
+                let style = owo_colors::Style::new().color(color);
+                format!("{:.6}", shown.style(if opts.bold { style.bold() } else { style }))
+            } else if opts.bold && opts.output.is_none() {
+                format!("{:.6}", shown.bold())
+            } else {
+                format!("{:.6}", shown)
+            };
+            let mut line = format!(
+                "{}\t{:width$}\t{}",
+                score,
+                absolute(x),
+                absolute(y),
+                width = widest_name
+            );
+            if !opts.weight.is_empty() {
+                let weight = weights[x].max(weights[y]);
+                line.push_str(&format!("\t(weight {})", weight));
+            }
+            if let Some(label) = baseline_label {
+                line.push_str(&format!("\t(baseline {})", label));
+            }
+            if let (Some(a), Some(b)) = (side_of(x), side_of(y)) {
+                line.push_str(&format!("\t({} vs {})", a, b));
+            }
+            if let Some(unusual) = unusual {
+                line.push_str(&format!("\t({:+.2} sd from the median)", unusual));
+            }
+            if opts.granular.is_some() {
+                // worked out again too, same as below
+                let scorer = opts.algorithm.scorer_with(rarity.clone());
+                let (_, granularity) = similarity::best_granular(&*scorer, &prepared[x], &prepared[y]);
+                line.push_str(&format!("\t({})", granularity.name()));
+            }
+            if opts.try_all_normalizations {
+                // worked out again, since it's only needed for the few pairs that get shown
+                let scorer = opts.algorithm.scorer_with(rarity.clone());
+                let (_, variant) = similarity::best_score(&*scorer, &prepared[x], &prepared[y]);
+                line.push_str(&format!("\t({})", normalize::VARIANTS[variant]));
+            }
+            line
+        };
+        let _ = writeln!(out, "{}", line);
+        if opts.show_lcs && opts.template_str.is_none() {
+            let common = analysis::longest_common_substring(&files[x], &files[y]);
+            let _ = writeln!(out, "\tLongest common text, {} characters:", common.chars().count());
+            for line in common.lines().take(LCS_SHOWN_LINES) {
+                let _ = writeln!(out, "\t| {}", line);
+            }
+            if common.lines().count() > LCS_SHOWN_LINES {
+                let _ = writeln!(out, "\t| ...");
+            }
+        }
+        // functions and directories aren't in the originals
+        if let (true, Some(a), Some(b)) = (opts.template_str.is_none(), originals.get(x), originals.get(y)) {
+            for (a, b) in analysis::matching_ranges(a, b, RANGES_SHOWN) {
+                let _ = writeln!(
+                    out,
+                    "\tLines {}-{} match lines {}-{}",
+                    a.start(),
+                    a.end(),
+                    b.start(),
+                    b.end()
+                );
+            }
+        }
+    };
+    // scores are always similarities inside, so turn the bounds into similarities too.
+    // a lower bound on similarity is an upper bound on distance, so they just carry over.
+    let max_similarity = opts.max_sensitivity.map_or(f64::INFINITY, |max| metric.convert(max));
+    let below_max = |score: f64| under_max(score, max_similarity, opts.exclude_identical);
+    // with a fixed threshold, we can show pairs as soon as they're scored
+    let live_sensitivity = match opts.threshold {
+        Threshold::Fixed { sensitivity } => Some(metric.convert(sensitivity)),
+        Threshold::Percentile { .. } => None,
+    };
+    if opts.template_str.is_none() && !opts.ci {
+        log::info!(
+            "Scores are shown as {}, where {} means identical files.",
+            metric.name(),
+            metric.convert(1.0)
+        );
+    }
+    let grouped = opts.output_style == output::OutputStyle::Grouped && opts.template_str.is_none() && !opts.ci;
+    if grouped {
+        if groups.is_empty() {
+            log::warn!("--output-style grouped without any groups, use --group-by-parent or --group-regex.");
+        }
+        let _ = writeln!(out, "group_a\tfile_a\tgroup_b\tfile_b\t{}", metric.name());
+    }
+    // pairs can't be shown as they're found if they need sorting or capping first
+    let defer = opts.sort.is_some()
+        || grouped
+        || opts.max_pairs_per_file.is_some()
+        || opts.relative_scores
+        || opts.page.is_some();
+    let interactive = opts.output.is_none() && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let pager = |total: usize| output::Pager::new(opts.page.unwrap_or(usize::MAX), total, interactive);
 
-    /// Files or globs of files to compare.
-    #[bpaf(positional("FILE"))]
-    files: Vec<PathBuf>,
-}
+    let mut nearest = opts.knn.map(analysis::Nearest::new);
+    // the bar from --sensitivity-map, the lower one if the two files have different ones
+    let pair_sensitivity = |x: &PathBuf, y: &PathBuf, default: f64| {
+        let for_file = |path: &PathBuf| {
+            let path = unit_sources.get(path).unwrap_or(path);
+            path.extension()
+                .and_then(|extension| opts.sensitivity_map.get(&extension.to_string_lossy().to_ascii_lowercase()))
+                .map_or(default, |&sensitivity| metric.convert(sensitivity))
+        };
+        for_file(x).min(for_file(y))
+    };
+    // whether an in-window pair should actually be flagged
+    let reportable = |x: &PathBuf, y: &PathBuf| {
+        let long_enough = opts.min_pair_length == 0
+            || files[x].chars().count().min(files[y].chars().count()) >= opts.min_pair_length;
+        long_enough && !ignored_pairs.contains(&ordered_pair(x, y))
+    };
 
-/// Takes a list of paths and turns them into paths matching files
-fn filter_paths(globs: &Vec<PathBuf>) -> Vec<PathBuf> {
-    let mut files: Vec<PathBuf> = Vec::new();
-    for pattern in globs {
-        let pattern = pattern.as_os_str().to_string_lossy();
-        let paths = glob::glob(&pattern);
-        match paths {
-            Ok(paths) => {
-                let count = files.len();
-                files.extend(paths.filter_map(Result::ok));
-                if count == files.len() {
-                    log::warn!("\"{}\" didn't match any files.", &pattern);
-                }
+    // keep a copy around to check the threaded results against
+    let serial_workqueue = opts.verify_serial.then(|| workqueue.clone());
+    let job_count = workqueue.len();
+    let mut compared = 0;
+    let mut histogram = output::Histogram::default();
+    // the estimate's only used if there are too many pairs past the percentile to keep
+    let (mut top, mut estimate) = match opts.threshold {
+        Threshold::Percentile { percentile: p } if !keep_scores => (
+            Some(analysis::TopScores::new(top_needed(job_count, p, metric))),
+            Some(analysis::Quantile::new(p / 100.0)),
+        ),
+        _ => (None, None),
+    };
+    let bar = if job_count > 1 && !opts.no_progress {
+        ProgressBar::new(job_count as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    // with -v, say which pair just finished, so one huge file holding things up stands out
+    if opts.verbose {
+        bar.set_style(
+            ProgressStyle::with_template("{wide_bar} {pos}/{len} {wide_msg}")
+                .expect("progress bar template is valid"),
+        );
+    }
+    let order = opts.sort.unwrap_or(output::SortOrder::similar_first(metric));
+    let round = |score: f64| {
+        opts.round_to.map_or(score, |places| {
+            let scale = 10f64.powi(places);
+            (score * scale).round() / scale
+        })
+    };
+    let on_score = keeps_paths(|x, y, score| {
+        let score = round(score);
+        compared += 1;
+        if keep_scores {
+            scores.insert((x.clone(), y.clone()), score);
+        }
+        if let Some(top) = &mut top {
+            top.insert(x, y, score);
+        }
+        if let Some(estimate) = &mut estimate {
+            estimate.insert(metric.convert(score));
+        }
+        if opts.ascii_histogram {
+            histogram.add(metric.convert(score));
+        }
+        let past_threshold = live_sensitivity.is_some_and(|sensitivity| score >= pair_sensitivity(x, y, sensitivity));
+        if past_threshold && below_max(score) && reportable(x, y)
+        {
+            flagged.push((x, y, score));
+            if let Some(nearest) = &mut nearest {
+                nearest.insert(x, y, score);
+            } else if !defer && !opts.flag_files && !opts.worst && !opts.aggregate_by_file {
+                bar.suspend(|| write_pair(&mut *out, x, y, score));
             }
-            Err(err) => {
+        } else if job_count == 1 && reportable(x, y) {
+            // with just two files the score's the whole point, flagged or not
+            write_pair(&mut *out, x, y, score);
+        }
+        if opts.verbose {
+            bar.set_message(format!("{} and {}", x.to_string_lossy(), y.to_string_lossy()));
+        }
+        bar.inc(1);
+    });
+    // pairs that can't reach the lowest bar don't need scoring, as long as nothing
+    // wants their real scores. rounding could push a score up past the bar, so not then either.
+    // a lone pair's score gets shown whatever it is
+    let skip_below = live_sensitivity
+        .filter(|_| !keep_scores && !opts.ascii_histogram && opts.round_to.is_none() && job_count > 1)
+        .map(|sensitivity| {
+            opts.sensitivity_map.values().map(|&sensitivity| metric.convert(sensitivity)).fold(sensitivity, f64::min)
+        });
+    if let Some(floor) = skip_below {
+        log::debug!("Skipping pairs that can't reach a similarity of {}.", floor);
+    }
+    // only the comparisons, loading files is its own thing
+    #[cfg(feature = "profiling")]
+    let profiler = opts.profile_output.as_ref().and_then(|_| {
+        pprof::ProfilerGuardBuilder::default()
+            .frequency(PROFILE_FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()
+            .map_err(|err| log::warn!("Couldn't start profiling. ({})", err))
+            .ok()
+    });
+    if let Some(imported) = &imported {
+        // logged the way they were shown
+        let mut pairs: Vec<(&PathBuf, &PathBuf, f64)> = imported
+            .iter()
+            .filter_map(|((x, y), &score)| {
+                Some((files.get_key_value(x)?.0, files.get_key_value(y)?.0, metric.convert(score)))
+            })
+            .collect();
+        pairs.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        let mut on_score = on_score;
+        for (x, y, score) in pairs {
+            on_score(x, y, score);
+        }
+    } else {
+        let comparing = Comparing {
+            skip_below,
+            throttle: opts.max_cps.map(|per_second| Arc::new(Throttle::new(per_second))),
+            ..Comparing::new(opts.algorithm, rarity.clone())
+        };
+        if opts.no_preload {
+            let cache = cache::FileCache::new(&opts, &loading, opts.cache_files);
+            compare_on_demand(workqueue, &cache, &comparing, opts.jobs, &stop, on_score);
+        } else {
+            compare_all(workqueue, &prepared, &comparing, opts.jobs, &stop, on_score);
+        }
+    }
+    bar.finish();
+    timings.finish("Comparing");
+    #[cfg(feature = "profiling")]
+    if let (Some(profiler), Some(path)) = (profiler, &opts.profile_output) {
+        let written = profiler.report().build().map_err(|err| err.to_string()).and_then(|report| {
+            let file = File::create(path).map_err(|err| err.to_string())?;
+            report.flamegraph(file).map_err(|err| err.to_string())
+        });
+        match written {
+            Ok(()) => log::info!("Saved the profile to {}.", path.to_string_lossy()),
+            Err(err) => log::error!("Couldn't save the profile to {}. ({})", path.to_string_lossy(), err),
+        }
+    }
+    // a stop is either ctrl-c or running out of time, the results are incomplete either way
+    let stopped = stop.load(Ordering::Relaxed);
+    let interrupted = stopped && !out_of_time.load(Ordering::Relaxed);
+    if interrupted {
+        log::warn!(
+            "Interrupted, only {} of {} comparisons were made.",
+            compared,
+            job_count
+        );
+    } else if stopped && compared < job_count {
+        log::warn!(
+            "Ran out of time, only {} of {} comparisons were made. These results are incomplete.",
+            compared,
+            job_count
+        );
+    }
+    if opts.relative_scores {
+        if let Some(median) = percentile(scores.values().copied(), 50.0) {
+            let mean = scores.values().sum::<f64>() / scores.len() as f64;
+            let variance = scores.values().map(|score| (score - mean).powi(2)).sum::<f64>() / scores.len() as f64;
+            log::info!(
+                "The median pair has a {} of {:.6}, and the standard deviation is {:.6}.",
+                metric.name(),
+                metric.convert(median),
+                variance.sqrt()
+            );
+            // all the same score, so nothing's unusual
+            if variance > 0.0 {
+                spread.set(Some((median, variance.sqrt())));
+            }
+        }
+    }
+
+    let sensitivity = match opts.threshold {
+        Threshold::Fixed { sensitivity } => metric.convert(sensitivity),
+        Threshold::Percentile { percentile: p } => {
+            let from_top = top.is_some();
+            let exact = top.as_ref().is_none_or(analysis::TopScores::is_exact);
+            let candidates: Vec<(&PathBuf, &PathBuf, f64)> = match top.take() {
+                Some(top) => top.into_sorted(),
+                None => scores.iter().map(|((x, y), &score)| (x, y, score)).collect(),
+            };
+            // the percentile is of the scores as the user sees them
+            let shown = if !exact {
                 log::warn!(
-                    "\"{}\" is not a valid pattern, and will be ignored. ({})",
-                    &pattern,
-                    &err.msg
+                    "Only the {} most similar pairs were kept, so the {}th percentile is estimated and \
+                    only those pairs can be flagged.",
+                    analysis::MAX_TOP_SCORES,
+                    p
                 );
+                estimate.as_ref().and_then(analysis::Quantile::estimate)
+            } else if from_top {
+                percentile_from_top(candidates.iter().map(|&(_, _, score)| score), compared, p, metric)
+            } else {
+                percentile(candidates.iter().map(|&(_, _, score)| metric.convert(score)), p)
+            };
+            let sensitivity = shown.map_or(f64::INFINITY, |sensitivity| metric.convert(sensitivity));
+            log::info!("The {}th percentile is a {} of {:.6}.", p, metric.name(), metric.convert(sensitivity));
+            // now that we know the threshold, show everything above it, most similar first
+            let mut window: Vec<_> = candidates
+                .into_iter()
+                .filter(|&(x, y, score)| score >= sensitivity && below_max(score) && reportable(x, y))
+                .collect();
+            window.sort_unstable_by(|a, b| order.compare(metric, (a.0, a.1, a.2), (b.0, b.1, b.2)));
+            if let Some(nearest) = &mut nearest {
+                for &(x, y, score) in &window {
+                    nearest.insert(x, y, score);
+                }
+            } else if !opts.flag_files && !opts.worst && !opts.aggregate_by_file {
+                let shown = capped(&window, opts.max_pairs_per_file);
+                let mut pager = pager(shown.len());
+                for (x, y, score) in shown {
+                    if !pager.next(&mut *out) {
+                        break;
+                    }
+                    write_pair(&mut *out, x, y, score);
+                }
             }
+            flagged.extend(window);
+            sensitivity
         }
+    };
+    if opts.worst {
+        let worst = scores
+            .iter()
+            .filter(|((x, y), &score)| below_max(score) && reportable(x, y))
+            .max_by(|a, b| a.1.total_cmp(b.1).then_with(|| b.0.cmp(a.0)));
+        match worst {
+            Some(((x, y), &score)) => write_pair(&mut *out, x, y, score),
+            None => log::info!("No pairs were compared."),
+        }
+        let _ = out.flush();
+        return;
     }
-    files
-        .iter()
-        .map(std::fs::canonicalize)
-        .filter_map(Result::ok)
-        .collect()
-}
-
-/// Loads a file to a string, handling non-utf-8 encoding
-fn load_file(path: &PathBuf, program: &CliArgs) -> anyhow::Result<String> {
-    let mut file = File::open(path)?;
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes)?;
-    let encoding = chardet::detect(&bytes).0;
-    let encoding = Encoding::for_label(encoding.as_bytes()).unwrap_or(encoding_rs::UTF_8);
-    let mut loaded_file = encoding.decode(&bytes).0.to_string();
-    // filter out whitespace characters
-    if program.trim {
-        loaded_file = loaded_file.chars()
-            .filter(|x| !x.is_whitespace()).collect();
+    // held back so they could be sorted
+    if defer
+        && nearest.is_none()
+        && !opts.flag_files
+        && !opts.aggregate_by_file
+        && matches!(opts.threshold, Threshold::Fixed { .. })
+    {
+        flagged.sort_unstable_by(|a, b| order.compare(metric, (a.0, a.1, a.2), (b.0, b.1, b.2)));
+        let shown = capped(&flagged, opts.max_pairs_per_file);
+        let mut pager = pager(shown.len());
+        for (x, y, score) in shown {
+            if !pager.next(&mut *out) {
+                break;
+            }
+            write_pair(&mut *out, x, y, score);
+        }
     }
-    Ok(loaded_file)
-}
 
-fn main() {
-    // --- Process arguments and file list
-    let mut opts = cli_args().run();
-    // autodetect parallelism if set to 0
-    if opts.jobs == 0 {
-        opts.jobs = thread::available_parallelism()
-            .unwrap_or(NonZeroUsize::new(1).unwrap())
-            .into();
+    // the bar usually says this
+    if opts.no_progress {
+        log::info!("Compared {} pairs, {} flagged.", compared, flagged.len());
     }
-    let opts = opts;
-    // initialize logger based on chosen debug level
-    if opts.verbose {
-        pretty_env_logger::formatted_builder()
-            .filter_level(Debug)
-            .init();
-    } else {
-        pretty_env_logger::formatted_builder()
-            .filter_level(Info)
-            .init();
+    // so nobody has to wonder whether it finished
+    if flagged.is_empty() && !stopped {
+        log::info!(
+            "No pairs found with a {} of {} {:.6}.",
+            metric.name(),
+            metric.bound(),
+            metric.convert(sensitivity)
+        );
+        // a lone pair was already shown anyway
+        if job_count > 1 {
+            if let Err(err) = output::write_nothing_flagged(&mut out, opts.format) {
+                log::error!("Couldn't write the empty results. ({})", err);
+            }
+        }
     }
-    let paths = filter_paths(&opts.files);
-    // make sure we have enough files
-    if paths.len() <= 1 {
-        log::error!("Got {} files to compare, need at least 2.", paths.len());
-        return;
-    } else {
-        log::info!("Got {} files to compare.", paths.len())
+
+    // when most pairs get flagged, the flags don't say much
+    if compared >= MIN_PAIRS_FOR_RATIO && flagged.len() as f64 > compared as f64 * MAX_FLAGGED_RATIO {
+        log::warn!(
+            "{} of {} pairs were flagged ({:.0}%), the sensitivity is probably too {}. \
+             Try a stricter one, or --common-lines to find shared template code.",
+            flagged.len(),
+            compared,
+            flagged.len() as f64 / compared as f64 * 100.0,
+            match metric {
+                output::Metric::Similarity => "low",
+                output::Metric::Distance => "high",
+            }
+        );
     }
-    let mut logfile: Option<File> = opts
-        .logfile
-        .clone()
-        .and_then(|path| File::create(path).ok());
 
-    // --- Compare files
-    // preload all files into memory
-    let mut files: HashMap<PathBuf, String> = HashMap::new();
-    let mut widest_name = 0;
-    for path in &paths {
-        if !path.is_file() {
-            log::debug!("{} wasn't a file.", path.to_str().unwrap_or("<error>"));
-            continue;
+    if let Some(nearest) = nearest {
+        for (file, neighbors) in nearest.into_sorted() {
+            let _ = writeln!(out, "{}", absolute(file));
+            for (other, score) in neighbors {
+                let _ = writeln!(out, "\t{:.6}\t{}", metric.convert(score), absolute(other));
+            }
         }
-        files.insert(path.clone(), load_file(path, &opts).unwrap());
-        // find the widest name for printing later
-        widest_name = widest_name.max(path.as_os_str().to_string_lossy().len());
     }
 
-    // hashmap for storing scores
-    let mut scores: HashMap<(PathBuf, PathBuf), f64> = HashMap::new();
+    if opts.entropy {
+        let mut by_entropy: Vec<(&PathBuf, f64)> = entropies.iter().map(|(path, &entropy)| (path, entropy)).collect();
+        by_entropy.sort_unstable_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .expect("Couldn't compare two entropies")
+                .then_with(|| a.0.cmp(b.0))
+        });
+        let quartiles = percentile(by_entropy.iter().map(|&(_, entropy)| entropy), 25.0)
+            .zip(percentile(by_entropy.iter().map(|&(_, entropy)| entropy), 75.0));
+        let low = quartiles.map_or(f64::NEG_INFINITY, |(q1, q3)| q1 - 1.5 * (q3 - q1));
+        let _ = writeln!(out, "Entropy, in bits per character:");
+        for (path, entropy) in by_entropy {
+            let marker = if entropy < low { "\t(unusually low)" } else { "" };
+            let _ = writeln!(out, "{:.3}\t{}{}", entropy, absolute(path), marker);
+        }
+    }
 
-    // queue of comparisons that need to be made
-    let mut workqueue: Vec<(&PathBuf, &PathBuf)> = Vec::new();
-    for x in files.keys() {
-        for y in files.keys() {
-            // skip this comparison if we've already compared the two in opposite direction
-            // or if it's the same file twice
-            if x >= y {
-                continue;
+    if opts.flag_files {
+        // other files in the same group were never compared, so these are all cross-group
+        let mut best: HashMap<&PathBuf, f64> = HashMap::new();
+        for ((x, y), &score) in &scores {
+            for file in [x, y] {
+                let entry = best.entry(file).or_insert(score);
+                *entry = entry.max(score);
             }
-            workqueue.push((x, y));
+        }
+        let flagged_files: HashSet<&PathBuf> = flagged.iter().flat_map(|&(x, y, _)| [x, y]).collect();
+        for &path in &sorted_paths {
+            let _ = writeln!(
+                out,
+                "{}\t{}\t{}",
+                if flagged_files.contains(path) { "yes" } else { "no" },
+                best.get(path).map_or("-".to_string(), |&score| format!("{:.6}", metric.convert(score))),
+                absolute(path)
+            );
         }
     }
 
-    let workqueue: Arc<Mutex<Vec<(&PathBuf, &PathBuf)>>> = Arc::new(Mutex::new(workqueue));
-    // channel for receiving results
+    if opts.aggregate_by_file {
+        let mut matches: HashMap<&PathBuf, Vec<(&PathBuf, f64)>> = HashMap::new();
+        for &(x, y, score) in &flagged {
+            matches.entry(x).or_default().push((y, score));
+            matches.entry(y).or_default().push((x, score));
+        }
+        for &path in &sorted_paths {
+            let Some(matches) = matches.get_mut(path) else {
+                continue;
+            };
+            matches.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            let _ = writeln!(out, "{} ({} matches)", absolute(path), matches.len());
+            for &(other, score) in matches.iter() {
+                let _ = writeln!(out, "\t{:.6}\t{}", metric.convert(score), absolute(other));
+            }
+        }
+    }
 
-    // spawn the threads
-    thread::scope(|scope| {
-        let (tx, rx) = mpsc::channel();
-        let job_count = workqueue.lock().unwrap().len();
-        // worker threads
-        for x in 0..opts.jobs {
-            let workqueue = workqueue.clone();
-            let tx = tx.clone();
-            // give the thread a name in case we have to debug specific threads later
-            thread::Builder::new()
-                .name(x.to_string())
-                .spawn_scoped(scope, || work(workqueue, &files, tx))
-                .unwrap();
-        }
-        // other thread
-        scope.spawn({
-            let scores = &mut scores;
-            move || {
-                let bar = ProgressBar::new(job_count as u64);
-                // loop runs once per message from the worker threads (blocking while waiting)
-                // and ends when all worker threads drop their Senders.
-                for (x, y, score) in rx.iter() {
-                    scores.insert((x.clone(), y.clone()), score);
-                    if score >= opts.sensitivity && score <= opts.max_sensitivity {
-                        // keep this import scoped small, otherwise everything gets
-                        // a billion color methods in rust-analyzer.
-                        use owo_colors::OwoColorize;
-                        // todo unique color per file?
-                        // formatted as 12.45678 (decimal place is 3) so 8 characters total, 5 after decimal thus 08.5
-                        bar.suspend(|| {
-                            println!(
-                                "{:.6}\t{:width$}\t{}",
-                                score.color(get_color(0.3, score, 1.0)),
-                                x.to_string_lossy(),
-                                y.to_string_lossy(),
-                                width = widest_name
-                            )
-                        });
-                    }
-                    bar.inc(1);
-                }
-                bar.finish();
+    if let Some(previous_run) = &previous_run {
+        // the old logfile has scores as they were shown
+        let old: HashMap<(PathBuf, PathBuf), f64> =
+            previous_run.iter().map(|(pair, &score)| (pair.clone(), metric.convert(score))).collect();
+        let new: HashMap<(PathBuf, PathBuf), f64> =
+            scores.iter().map(|((x, y), &score)| (ordered_pair(x, y), score)).collect();
+        let changes = analysis::delta(&old, &new, |score| score >= sensitivity && below_max(score));
+        let flagged_in = |score: Option<f64>| score.is_some_and(|score| score >= sensitivity && below_max(score));
+        let shown = |score: Option<f64>| score.map_or("-".to_string(), |score| format!("{:.6}", metric.convert(score)));
+        for (heading, was, is) in [
+            ("Newly flagged:", false, true),
+            ("No longer flagged:", true, false),
+            ("Still flagged:", true, true),
+        ] {
+            let _ = writeln!(out, "{}", heading);
+            for change in changes.iter().filter(|change| flagged_in(change.old) == was && flagged_in(change.new) == is) {
+                let _ = writeln!(
+                    out,
+                    "\t{}\t(was {})\t{}\t{}",
+                    shown(change.new),
+                    shown(change.old),
+                    absolute(change.a),
+                    absolute(change.b)
+                );
             }
+        }
+    }
+
+    if opts.group_summary {
+        if !opts.group_by_parent && opts.group_regex.is_none() {
+            log::warn!("--group-summary doesn't do anything without --group-by-parent or --group-regex.");
+        }
+        let summary = analysis::group_summary(
+            scores.iter().map(|((x, y), &score)| (x, y, score)),
+            |path| groups.get(path).map(|group| group.as_ref()),
+        );
+        let _ = writeln!(out, "Closest match for each group:");
+        for (group, other, score) in summary {
+            let _ = writeln!(
+                out,
+                "{:.6}\t{}\t{}",
+                metric.convert(score),
+                absolute(group),
+                absolute(other)
+            );
+        }
+    }
+
+    if opts.ascii_histogram {
+        let width = console::Term::stdout().size_checked().map_or(80, |(_, columns)| columns as usize);
+        let _ = writeln!(out, "Scores ({}):", metric.name());
+        let _ = output::write_histogram(&mut out, &histogram, width);
+    }
+
+    // lots of pairs with the exact same score usually means shared boilerplate.
+    // only checked when every score was kept
+    if opts.verbose {
+        for (score, count) in analysis::score_ties(scores.values().copied()) {
+            log::debug!(
+                "{} pairs have a {} of exactly {:.6}, the files probably share a template. \
+                 Try --common-lines to find it, or use a stricter sensitivity.",
+                count,
+                metric.name(),
+                metric.convert(score)
+            );
+        }
+    }
+
+    // run everything again on one thread, the results should be exactly the same
+    if let Some(workqueue) = serial_workqueue.filter(|_| !stopped) {
+        let mut serial_scores = HashMap::with_capacity(scores.len());
+        let comparing = Comparing::new(opts.algorithm, rarity.clone());
+        compare_all(workqueue, &prepared, &comparing, 1, &stop, |x, y, score| {
+            serial_scores.insert((x.clone(), y.clone()), round(score));
         });
-    });
+        let mut differing: Vec<&(PathBuf, PathBuf)> = scores
+            .keys()
+            .chain(serial_scores.keys())
+            .filter(|pair| scores.get(*pair) != serial_scores.get(*pair))
+            .collect();
+        differing.sort_unstable();
+        differing.dedup();
+        if !differing.is_empty() {
+            let shown = |score: Option<&f64>| {
+                score.map_or("nothing".to_string(), |&score| format!("{:.6}", metric.convert(score)))
+            };
+            for pair @ (x, y) in differing.iter().copied() {
+                log::error!(
+                    "{} and {} scored {} threaded, but {} serially.",
+                    x.to_string_lossy(),
+                    y.to_string_lossy(),
+                    shown(scores.get(pair)),
+                    shown(serial_scores.get(pair))
+                );
+            }
+            log::error!("Threaded and serial scores differ for {} pairs.", differing.len());
+            std::process::exit(2);
+        }
+        log::info!("Threaded and serial scores match.");
+    }
 
     // write to logfile of scores, sorted
-    if let Some(logfile) = &mut logfile {
+    let write_log = |logfile: &mut File, scores: &[(&(PathBuf, PathBuf), &f64)]| match opts.format {
+        output::LogFormat::Csv => {
+            for ((x, y), score) in scores {
+                let _ = writeln!(
+                    logfile,
+                    "{:.6},{},{}",
+                    metric.convert(**score),
+                    x.to_string_lossy(),
+                    y.to_string_lossy(),
+                );
+            }
+        }
+        output::LogFormat::Moss => {
+            let pairs: Vec<_> = scores
+                .iter()
+                .map(|((x, y), &score)| (x.as_path(), relative(x), y.as_path(), relative(y), score))
+                .collect();
+            if let Err(err) = output::write_moss(logfile, &pairs) {
+                log::error!("Couldn't write the logfile. ({})", err);
+            }
+        }
+        output::LogFormat::SheetCsv => {
+            let pairs: Vec<_> = scores
+                .iter()
+                .map(|((x, y), &score)| (x.as_path(), relative(x), y.as_path(), relative(y), metric.convert(score)))
+                .collect();
+            if let Err(err) = output::write_sheet_csv(logfile, &pairs) {
+                log::error!("Couldn't write the logfile. ({})", err);
+            }
+        }
+        output::LogFormat::Json => {
+            // absolute paths, same as csv
+            let pairs: Vec<_> = scores
+                .iter()
+                .map(|((x, y), &score)| {
+                    (x.as_path(), x.to_string_lossy().into_owned(), y.as_path(), y.to_string_lossy().into_owned(), score)
+                })
+                .collect();
+            if let Err(err) = output::write_json(logfile, &pairs, None, metric) {
+                log::error!("Couldn't write the logfile. ({})", err);
+            }
+        }
+    };
+    if logfile.is_some() || flagged_log.is_some() {
         let mut scores = scores.iter().collect::<Vec<_>>();
-        // sort in descending order by flipping the closure
-        scores.sort_unstable_by(|a, b| b.1.partial_cmp(a.1).expect("Couldn't compare two scores"));
+        let compare = |a: &(&(PathBuf, PathBuf), &f64), b: &(&(PathBuf, PathBuf), &f64)| {
+            order.compare(metric, (&a.0 .0, &a.0 .1, *a.1), (&b.0 .0, &b.0 .1, *b.1))
+        };
+        #[cfg(feature = "rayon")]
+        if scores.len() >= PARALLEL_SORT_MIN {
+            use rayon::slice::ParallelSliceMut;
+            scores.par_sort_unstable_by(compare);
+        } else {
+            scores.sort_unstable_by(compare);
+        }
+        #[cfg(not(feature = "rayon"))]
+        scores.sort_unstable_by(compare);
         // scores are sorted, log them in order
-        for ((x, y), score) in &scores {
-            let _ = writeln!(
-                logfile,
-                "{:.6},{},{}",
-                score,
-                x.to_string_lossy(),
-                y.to_string_lossy(),
-            );
+        if let Some(logfile) = &mut logfile {
+            let logged: Vec<_> = scores
+                .iter()
+                .copied()
+                .filter(|(_, &score)| opts.log_threshold.is_none_or(|threshold| score >= metric.convert(threshold)))
+                .collect();
+            write_log(logfile, &logged);
+        }
+        if let Some(flagged_log) = &mut flagged_log {
+            let in_window: HashSet<(&PathBuf, &PathBuf)> = flagged.iter().map(|&(x, y, _)| (x, y)).collect();
+            let logged: Vec<_> = scores.iter().copied().filter(|((x, y), _)| in_window.contains(&(x, y))).collect();
+            write_log(flagged_log, &logged);
+        }
+    }
+
+    // most similar first for the reports, same as the logfile
+    flagged.sort_unstable_by(|a, b| {
+        b.2.partial_cmp(&a.2)
+            .expect("Couldn't compare two scores")
+            .then_with(|| (a.0, a.1).cmp(&(b.0, b.1)))
+    });
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &opts.sqlite {
+        let files = sorted_paths.iter().copied().map(|path| (path, groups.get(path).map(|group| group.as_ref())));
+        let algorithm = format!("{:?}", opts.algorithm);
+        match sqlite::write(path, files, &scores, &algorithm, metric.name(), metric.convert(sensitivity)) {
+            Ok(run) => log::info!("Saved this run to {} as run {}.", path.to_string_lossy(), run),
+            Err(err) => log::error!("Couldn't save the results to {}. ({})", path.to_string_lossy(), err),
+        }
+    }
+    if let Some(path) = &opts.junit {
+        let flagged: Vec<_> = flagged
+            .iter()
+            .map(|(x, y, score)| (relative(x), relative(y), *score))
+            .collect();
+        if let Err(err) = File::create(path)
+            .and_then(|mut file| output::write_junit(&mut file, &flagged, metric.convert(sensitivity), metric))
+        {
+            log::error!("Couldn't write the JUnit report to {}. ({})", path.to_string_lossy(), err);
+        }
+    }
+    if let Some(path) = &opts.originality {
+        let mut best: HashMap<&PathBuf, f64> = HashMap::new();
+        for ((x, y), &score) in &scores {
+            for file in [x, y] {
+                let entry = best.entry(file).or_insert(score);
+                *entry = entry.max(score);
+            }
+        }
+        let rows: Vec<_> = sorted_paths
+            .iter()
+            .map(|&path| (path.to_string_lossy().into_owned(), best.get(path).map(|&score| 1.0 - score)))
+            .collect();
+        if let Err(err) = File::create(path).and_then(|mut file| output::write_originality(&mut file, &rows)) {
+            log::error!("Couldn't write the originality scores to {}. ({})", path.to_string_lossy(), err);
+        }
+    }
+    if let Some(path) = &opts.heatmap {
+        let ordered = heatmap::order(&sorted_paths, &scores);
+        let score = |i: usize, j: usize| {
+            let (x, y) = (ordered[i].clone(), ordered[j].clone());
+            scores.get(&(x.clone(), y.clone())).or_else(|| scores.get(&(y, x))).copied()
+        };
+        let color = |score: f64| {
+            let Rgb(r, g, b) = get_color(0.0, score, 1.0, opts.color_threshold_low, opts.color_threshold_high);
+            [r, g, b]
+        };
+        let mut legend = path.clone().into_os_string();
+        legend.push(".legend.txt");
+        let names: Vec<String> = ordered.iter().map(|path| relative(path)).collect();
+        if let Err(err) = heatmap::write(path, ordered.len(), score, color) {
+            log::error!("Couldn't write the heatmap to {}. ({})", path.to_string_lossy(), err);
+        } else if let Err(err) = heatmap::write_legend(Path::new(&legend), &names) {
+            log::error!("Couldn't write the heatmap's legend to {}. ({})", legend.to_string_lossy(), err);
+        }
+    }
+
+    // exiting skips destructors, so make sure everything's actually written
+    let _ = out.flush();
+    timings.finish("Writing results");
+    if opts.timings {
+        timings.print();
+    }
+    if let Some(path) = &opts.summary_json {
+        let flagged: Vec<_> = flagged
+            .iter()
+            .map(|(x, y, score)| (relative(x), relative(y), *score))
+            .collect();
+        let seconds: Vec<_> = timings.phases.iter().map(|&(phase, time)| (phase, time.as_secs_f64())).collect();
+        let summary = output::Summary {
+            files: files.len(),
+            compared,
+            complete: !stopped,
+            metric,
+            sensitivity: metric.convert(sensitivity),
+            flagged: &flagged,
+            seconds: &seconds,
+        };
+        if let Err(err) = File::create(path).and_then(|mut file| output::write_summary(&mut file, &summary)) {
+            log::error!("Couldn't write the summary to {}. ({})", path.to_string_lossy(), err);
+        }
+    }
+    if let Some(path) = &opts.manifest {
+        // split up files are hashed as the whole file they came from
+        let mut sources: Vec<&PathBuf> = sorted_paths.iter().map(|&path| unit_sources.get(path).unwrap_or(path)).collect();
+        sources.sort();
+        sources.dedup();
+        let inputs: Vec<(String, Option<String>)> = sources
+            .into_iter()
+            .map(|source| match read_bytes(source, &opts) {
+                Ok(bytes) => (absolute(source), Some(output::sha256(&bytes))),
+                Err(err) => {
+                    log::warn!("Couldn't hash {} for the manifest. ({:#})", source.to_string_lossy(), err);
+                    (absolute(source), None)
+                }
+            })
+            .collect();
+        let flagged: Vec<_> = flagged
+            .iter()
+            .map(|(x, y, score)| (absolute(x), absolute(y), *score))
+            .collect();
+        let manifest = output::Manifest {
+            version: env!("CARGO_PKG_VERSION"),
+            arguments: std::env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            options: format!("{:?}", opts),
+            cwd: cwd.to_string_lossy().into_owned(),
+            inputs: &inputs,
+            compared,
+            complete: !stopped,
+            metric,
+            sensitivity: metric.convert(sensitivity),
+            flagged: &flagged,
+        };
+        if let Err(err) = File::create(path).and_then(|mut file| output::write_manifest(&mut file, &manifest)) {
+            log::error!("Couldn't write the manifest to {}. ({})", path.to_string_lossy(), err);
+        }
+    }
+
+    if let Some(port) = opts.serve {
+        let pairs: Vec<_> = flagged
+            .iter()
+            .map(|&(x, y, score)| (x.as_path(), relative(x), y.as_path(), relative(y), score))
+            .collect();
+        let details: Option<Vec<output::PairDetails>> = opts.json_details.then(|| {
+            let scorer = opts.algorithm.scorer_with(rarity.clone());
+            flagged
+                .iter()
+                .map(|&(x, y, _)| output::PairDetails {
+                    lengths: (files[x].chars().count(), files[y].chars().count()),
+                    ranges: originals
+                        .get(x)
+                        .zip(originals.get(y))
+                        .map(|(a, b)| analysis::matching_ranges(a, b, RANGES_SHOWN)),
+                    variant: opts.try_all_normalizations.then(|| {
+                        normalize::VARIANTS[similarity::best_score(&*scorer, &prepared[x], &prepared[y]).1]
+                    }),
+                })
+                .collect()
+        });
+        // ctrl-c during the comparisons already set this, from here on it means stop serving
+        stop.store(false, Ordering::Relaxed);
+        if let Err(err) = serve::serve(port, &pairs, details.as_deref(), metric, &stop) {
+            log::error!("Couldn't serve the results on port {}. ({})", port, err);
         }
     }
+
+    // partial results are written, but still let the shell know we didn't finish
+    if interrupted {
+        std::process::exit(130);
+    }
+    if opts.ci && !flagged.is_empty() {
+        std::process::exit(1);
+    }
 }
 
-fn get_color(min: f64, score: f64, max: f64) -> impl DynColor {
+fn get_color(min: f64, score: f64, max: f64, low: Rgb, high: Rgb) -> Rgb {
     // colors are weird, man
     let Ok(gradient) = CustomGradient::new()
         .colors(&[
-            Color::from_rgba8(124, 249, 42, 255),
-            Color::from_rgba8(255, 16, 53, 255)
+            Color::from_rgba8(low.0, low.1, low.2, 255),
+            Color::from_rgba8(high.0, high.1, high.2, 255)
         ])
         .mode(colorgrad::BlendMode::Oklab)
         .interpolation(colorgrad::Interpolation::CatmullRom)
@@ -257,30 +3866,121 @@ fn get_color(min: f64, score: f64, max: f64) -> impl DynColor {
     Rgb(r,g,b)
 }
 
-/// Make comparisons until the workqueue is empty
-fn work<'a>(
-    jobs: Arc<Mutex<Vec<(&'a PathBuf, &'a PathBuf)>>>,
-    files: &HashMap<PathBuf, String>,
-    results: Sender<(&'a PathBuf, &'a PathBuf, f64)>,
+/// Lets only so many threads through at once
+struct Semaphore {
+    available: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Semaphore {
+    fn new(count: usize) -> Self {
+        Semaphore { available: Mutex::new(count), freed: Condvar::new() }
+    }
+
+    /// Waits for a turn, then runs `f`
+    fn with<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut available = self.freed.wait_while(self.available.lock().unwrap(), |n| *n == 0).unwrap();
+        *available -= 1;
+        drop(available);
+        let result = f();
+        *self.available.lock().unwrap() += 1;
+        self.freed.notify_one();
+        result
+    }
+}
+
+/// A loaded file, and the original too if --show-ranges needs it
+type Loaded = anyhow::Result<Option<(Option<String>, String)>>;
+
+/// Reads and normalizes files on --jobs threads, at most --max-open-files at a time.
+///
+/// The results are in the same order as `paths`.
+fn preload(paths: &[PathBuf], opts: &CliArgs, loading: &Loading, on_loaded: impl Fn() + Sync) -> Vec<Loaded> {
+    let next = AtomicUsize::new(0);
+    let reading = Semaphore::new(opts.max_open_files);
+    let mut loaded: Vec<Option<Loaded>> = (0..paths.len()).map(|_| None).collect();
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..opts.io_jobs.unwrap_or(opts.jobs).min(paths.len()) {
+            let tx = tx.clone();
+            let (next, reading, on_loaded) = (&next, &reading, &on_loaded);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(i) else {
+                    break;
+                };
+                // only reading needs the file open, normalizing can happen alongside other reads
+                let result = reading.with(|| read_text(path, opts)).and_then(|original| {
+                    let Some(original) = original else {
+                        return Ok(None);
+                    };
+                    let kept = opts.show_ranges.then(|| original.clone());
+                    let formatted = format_code(path, original, opts)?;
+                    Ok(formatted.map(|formatted| (kept, normalize_text(path, formatted, opts, loading))))
+                });
+                on_loaded();
+                if tx.send((i, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+        for (i, result) in rx.iter() {
+            loaded[i] = Some(result);
+        }
+    });
+    loaded.into_iter().map(|result| result.expect("every file was loaded")).collect()
+}
+
+/// Just gives back `f`, but written out like this it can keep the paths it's
+/// given, which a closure with `&PathBuf` arguments can't
+fn keeps_paths<'a, F: FnMut(&'a PathBuf, &'a PathBuf, f64)>(f: F) -> F {
+    f
+}
+
+/// Same as `compare_all`, but files are loaded as they're needed, for --no-preload.
+///
+/// Pairs with a file that couldn't be loaded are left out.
+fn compare_on_demand<'a>(
+    workqueue: Vec<(&'a PathBuf, &'a PathBuf)>,
+    files: &cache::FileCache,
+    comparing: &Comparing,
+    jobs: usize,
+    stop: &AtomicBool,
+    on_result: impl FnMut(&'a PathBuf, &'a PathBuf, f64),
 ) {
-    let lev = eddie::str::Levenshtein::new();
-    loop {
-        // lock() blocks the thread, the Result is just for if the mutex is poisoned
-        let job = jobs.lock().unwrap().pop();
-        match job {
-            None => break,
-            Some((x, y)) => {
-                let fx = files.get(x).unwrap();
-                let fy = files.get(y).unwrap();
-                let score = lev.similarity(fx, fy);
-                let _ = results.send((x, y, score));
-            }
+    let compare = |state: &mut WorkerState, x: &PathBuf, y: &PathBuf| {
+        let (a, b) = (files.get(x)?, files.get(y)?);
+        let (a, b) = (state.scorer.prepare(&a), state.scorer.prepare(&b));
+        Some(state.compare(&a, &b))
+    };
+    compare_with(workqueue, compare, comparing, jobs, stop, on_result);
+}
+
+/// How many lines are in each of --granular's runs of lines, which overlap by half
+const GRANULAR_WINDOW_LINES: usize = 10;
+/// Functions shorter than this aren't compared on their own, since lots of
+/// tiny ones like getters are the same everywhere
+const GRANULAR_MIN_FUNCTION_LINES: usize = 4;
+
+/// Cuts a file into the functions and runs of lines that --granular compares
+fn granular_parts(language: split::SplitLanguage, text: &str) -> Vec<(similarity::Granularity, String)> {
+    let mut parts: Vec<_> = split::functions(language, text)
+        .into_iter()
+        .filter(|(_, function)| {
+            function.lines().filter(|line| !line.trim().is_empty()).count() >= GRANULAR_MIN_FUNCTION_LINES
+        })
+        .map(|(_, function)| (similarity::Granularity::Function, function))
+        .collect();
+    let lines: Vec<&str> = text.lines().collect();
+    // a file that fits in one run is already compared whole
+    if lines.len() > GRANULAR_WINDOW_LINES {
+        for start in (0..=lines.len() - GRANULAR_WINDOW_LINES).step_by(GRANULAR_WINDOW_LINES / 2) {
+            let window = lines[start..start + GRANULAR_WINDOW_LINES].join("\n");
+            parts.push((similarity::Granularity::Window, window));
         }
     }
-    log::debug!(
-        "Worker thread {} exited.",
-        thread::current().name().unwrap()
-    );
+    parts
 }
 
 #[cfg(test)]
@@ -288,3 +3988,38 @@ fn work<'a>(
 fn check_opts() {
     cli_args().check_invariants(true);
 }
+
+#[cfg(test)]
+#[test]
+fn percentile_from_top_matches_percentile() {
+    let paths: Vec<PathBuf> = (0..40).map(|i| PathBuf::from(i.to_string())).collect();
+    // lots of ties, the zeroes especially
+    let scores: Vec<f64> = (0..40).map(|i| if i % 3 == 0 { 0.0 } else { (i % 7) as f64 / 10.0 }).collect();
+    for metric in [output::Metric::Similarity, output::Metric::Distance] {
+        for p in [0.0, 1.0, 10.0, 33.3, 50.0, 90.0, 99.0, 100.0] {
+            let mut top = analysis::TopScores::new(top_needed(scores.len(), p, metric));
+            for (path, &score) in paths.iter().zip(&scores) {
+                top.insert(path, path, score);
+            }
+            let top = top.into_sorted().into_iter().map(|(_, _, score)| score);
+            assert_eq!(
+                percentile_from_top(top, scores.len(), p, metric),
+                percentile(scores.iter().map(|&score| metric.convert(score)), p),
+                "{}th percentile of {}",
+                p,
+                metric.name()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn max_sensitivity_is_inclusive() {
+    assert!(under_max(0.99, 0.99, false));
+    assert!(!under_max(0.990001, 0.99, false));
+    // no --max-sensitivity at all
+    assert!(under_max(1.0, f64::INFINITY, false));
+    assert!(!under_max(1.0, f64::INFINITY, true));
+    assert!(under_max(0.999999, f64::INFINITY, true));
+}
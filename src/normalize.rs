@@ -0,0 +1,352 @@
+//! Rewrites files before comparing them, so cosmetic changes don't affect the score.
+use regex::Regex;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Languages we know the keywords of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskLanguage {
+    Python,
+    C,
+    Java,
+    JavaScript,
+}
+
+impl FromStr for MaskLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "python" | "py" => Ok(MaskLanguage::Python),
+            "c" | "cpp" | "c++" => Ok(MaskLanguage::C),
+            "java" => Ok(MaskLanguage::Java),
+            "js" | "javascript" => Ok(MaskLanguage::JavaScript),
+            _ => Err(format!(
+                "don't know the keywords of \"{}\", supported: python, c, cpp, java, js",
+                s
+            )),
+        }
+    }
+}
+
+impl MaskLanguage {
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            MaskLanguage::Python => &[
+                "False", "None", "True", "and", "as", "assert", "async", "await", "break",
+                "class", "continue", "def", "del", "elif", "else", "except", "finally", "for",
+                "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or",
+                "pass", "raise", "return", "try", "while", "with", "yield", "self", "print",
+                "range", "len",
+            ],
+            // c and c++ together, since nobody's naming variables `class` in c anyways
+            MaskLanguage::C => &[
+                "auto", "bool", "break", "case", "catch", "char", "class", "const", "continue",
+                "default", "delete", "do", "double", "else", "enum", "extern", "false", "float",
+                "for", "goto", "if", "include", "inline", "int", "long", "namespace", "new",
+                "nullptr", "private", "protected", "public", "register", "return", "short",
+                "signed", "sizeof", "static", "struct", "switch", "template", "this", "throw",
+                "true", "try", "typedef", "typename", "union", "unsigned", "using", "virtual",
+                "void", "volatile", "while", "define", "std",
+            ],
+            MaskLanguage::Java => &[
+                "abstract", "assert", "boolean", "break", "byte", "case", "catch", "char",
+                "class", "const", "continue", "default", "do", "double", "else", "enum",
+                "extends", "false", "final", "finally", "float", "for", "if", "implements",
+                "import", "instanceof", "int", "interface", "long", "native", "new", "null",
+                "package", "private", "protected", "public", "return", "short", "static",
+                "super", "switch", "synchronized", "this", "throw", "throws", "true", "try",
+                "var", "void", "volatile", "while", "String", "System",
+            ],
+            MaskLanguage::JavaScript => &[
+                "async", "await", "break", "case", "catch", "class", "const", "continue",
+                "debugger", "default", "delete", "do", "else", "export", "extends", "false",
+                "finally", "for", "function", "if", "import", "in", "instanceof", "let", "new",
+                "null", "of", "return", "static", "super", "switch", "this", "throw", "true",
+                "try", "typeof", "undefined", "var", "void", "while", "yield", "console",
+            ],
+        }
+    }
+}
+
+/// Whether a word is a keyword in any of the languages we know
+pub fn is_keyword(word: &str) -> bool {
+    [MaskLanguage::Python, MaskLanguage::C, MaskLanguage::Java, MaskLanguage::JavaScript]
+        .iter()
+        .any(|language| language.keywords().contains(&word))
+}
+
+/// Replaces every identifier that isn't a keyword with `_`
+pub fn mask_identifiers(language: MaskLanguage, text: &str) -> String {
+    static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+    let identifier = IDENTIFIER.get_or_init(|| Regex::new(r"\b[A-Za-z_][A-Za-z0-9_]*\b").unwrap());
+    let keywords = language.keywords();
+    identifier
+        .replace_all(text, |captures: &regex::Captures| {
+            let word = &captures[0];
+            if keywords.contains(&word) {
+                word.to_string()
+            } else {
+                "_".to_string()
+            }
+        })
+        .into_owned()
+}
+
+/// Replaces every number with `0`, so changing constants doesn't change the score
+pub fn mask_numbers(text: &str) -> String {
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    // hex, binary, and decimal with a fraction or exponent, plus suffixes like 10L or 1.5f
+    let number = NUMBER.get_or_init(|| {
+        Regex::new(r"\b(?:0[xX][0-9a-fA-F_]+|0[bB][01_]+|\d[\d_]*(?:\.\d[\d_]*)?(?:[eE][+-]?\d+)?)[A-Za-z]*\b").unwrap()
+    });
+    number.replace_all(text, "0").into_owned()
+}
+
+/// Removes import, include and using lines, which are mostly the same for everyone
+pub fn strip_imports(language: MaskLanguage, text: &str) -> String {
+    static PATTERNS: OnceLock<[Regex; 4]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            r"(?m)^[ \t]*(?:from[ \t]+[\w.]+[ \t]+import[ \t]*(?:\([^)]*\)|.*)|import[ \t]+.*)$",
+            r#"(?m)^[ \t]*(?:#[ \t]*include[ \t]*[<"][^>"\n]*[>"]|using[ \t]+(?:namespace[ \t]+)?[\w:]+[ \t]*;|import[ \t]+[\w.:<>"]+[ \t]*;)[ \t]*$"#,
+            r"(?m)^[ \t]*import[ \t]+(?:static[ \t]+)?[\w.*]+[ \t]*;[ \t]*$",
+            r#"(?m)^[ \t]*(?:import\b[^;'"]*?['"][^'"\n]*['"]|(?:const|let|var)[ \t]+[\w{}, \t]+=[ \t]*require\([^)\n]*\))[ \t]*;?[ \t]*$"#,
+        ]
+        .map(|pattern| Regex::new(pattern).unwrap())
+    });
+    let pattern = match language {
+        MaskLanguage::Python => &patterns[0],
+        MaskLanguage::C => &patterns[1],
+        MaskLanguage::Java => &patterns[2],
+        MaskLanguage::JavaScript => &patterns[3],
+    };
+    // keep the newlines, so line structure stays the same
+    pattern
+        .replace_all(text, |captures: &regex::Captures| "\n".repeat(captures[0].matches('\n').count()))
+        .into_owned()
+}
+
+/// Rewrites indentation as one tab per level, and drops trailing whitespace and blank lines
+pub fn normalize_indentation(text: &str, tab_width: usize) -> String {
+    // width of each line's indentation in columns, and the rest of the line
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let content = line.trim_start();
+            let indent = line[..line.len() - content.len()]
+                .chars()
+                .fold(0, |width, c| match c {
+                    '\t' => (width / tab_width.max(1) + 1) * tab_width.max(1),
+                    _ => width + 1,
+                });
+            (indent, content)
+        })
+        .collect();
+    // one level is however much this file tends to indent by
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+    let level = lines.iter().fold(0, |level, &(indent, _)| gcd(level, indent)).max(1);
+    let mut normalized = String::with_capacity(text.len());
+    for (indent, content) in lines {
+        normalized.extend(std::iter::repeat_n('\t', indent / level));
+        normalized.push_str(content);
+        normalized.push('\n');
+    }
+    normalized
+}
+
+/// Which Unicode normalization form to put text in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Composes characters, so `e` plus a combining accent is the same as `é`
+    Nfc,
+    /// Also folds compatibility characters, like fullwidth letters and ligatures
+    Nfkc,
+}
+
+impl FromStr for UnicodeForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfkc" => Ok(UnicodeForm::Nfkc),
+            _ => Err(format!("unknown normalization form \"{}\", expected nfc or nfkc", s)),
+        }
+    }
+}
+
+pub fn unicode_normalize(form: UnicodeForm, text: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    match form {
+        UnicodeForm::Nfc => text.nfc().collect(),
+        UnicodeForm::Nfkc => text.nfkc().collect(),
+    }
+}
+
+/// Letters from other scripts that look just like ASCII ones, and what they look like
+const CONFUSABLES: &[(char, char)] = &[
+    // cyrillic
+    ('а', 'a'), ('в', 'B'), ('е', 'e'), ('к', 'k'), ('м', 'M'), ('н', 'H'), ('о', 'o'),
+    ('р', 'p'), ('с', 'c'), ('т', 'T'), ('у', 'y'), ('х', 'x'), ('і', 'i'), ('ј', 'j'),
+    ('ѕ', 's'), ('ԁ', 'd'), ('ԛ', 'q'), ('ԝ', 'w'), ('һ', 'h'), ('ӏ', 'l'),
+    ('А', 'A'), ('В', 'B'), ('Е', 'E'), ('К', 'K'), ('М', 'M'), ('Н', 'H'), ('О', 'O'),
+    ('Р', 'P'), ('С', 'C'), ('Т', 'T'), ('Х', 'X'), ('І', 'I'), ('Ј', 'J'), ('Ѕ', 'S'),
+    // greek
+    ('α', 'a'), ('ο', 'o'), ('ρ', 'p'), ('ν', 'v'), ('ι', 'i'), ('κ', 'k'), ('υ', 'u'),
+    ('Α', 'A'), ('Β', 'B'), ('Ε', 'E'), ('Ζ', 'Z'), ('Η', 'H'), ('Ι', 'I'), ('Κ', 'K'),
+    ('Μ', 'M'), ('Ν', 'N'), ('Ο', 'O'), ('Ρ', 'P'), ('Τ', 'T'), ('Υ', 'Y'), ('Χ', 'X'),
+    // punctuation that sneaks in from word processors
+    ('‘', '\''), ('’', '\''), ('“', '"'), ('”', '"'), ('‐', '-'), ('–', '-'), ('−', '-'),
+    ('\u{a0}', ' '),
+];
+
+/// Replaces look-alike characters with the ASCII ones they're pretending to be
+pub fn fold_homoglyphs(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            // invisible, so they can be sprinkled anywhere to break up matches
+            '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}' => None,
+            // fullwidth forms line up with ASCII
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xff01 + 0x21),
+            c if c.is_ascii() => Some(c),
+            c => Some(CONFUSABLES.iter().find(|(from, _)| *from == c).map_or(c, |&(_, to)| to)),
+        })
+        .collect()
+}
+
+/// How a language writes comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// `# like this`, for python and shell
+    Hash,
+    /// `// like this` and `/* like this */`
+    C,
+}
+
+impl FromStr for CommentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hash" | "#" | "python" | "py" | "shell" | "sh" => Ok(CommentStyle::Hash),
+            "c" | "cpp" | "c++" | "java" | "js" | "javascript" | "rust" | "go" => Ok(CommentStyle::C),
+            _ => Err(format!("unknown comment style \"{}\", expected hash or c", s)),
+        }
+    }
+}
+
+/// Removes comments, leaving strings that happen to contain comment markers alone
+pub fn strip_comments(style: CommentStyle, text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    // the quote character of the string we're in, if we're in one
+    let mut quote: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            stripped.push(c);
+            if c == '\\' {
+                stripped.extend(chars.next());
+            } else if c == q || c == '\n' {
+                quote = None;
+            }
+            continue;
+        }
+        match (style, c, chars.peek()) {
+            (_, '"' | '\'', _) => {
+                quote = Some(c);
+                stripped.push(c);
+            }
+            (CommentStyle::Hash, '#', _) | (CommentStyle::C, '/', Some('/')) => {
+                // keep the newline, so line structure stays the same
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            (CommentStyle::C, '/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                stripped.push(' ');
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Turns windows and old mac line endings into plain `\n`
+pub fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Drops the first `head` and last `tail` lines. Line endings are normalized
+/// first, so a `\r` doesn't count as its own line.
+pub fn skip_lines(text: &str, head: usize, tail: usize) -> String {
+    let text = normalize_line_endings(text);
+    let lines: Vec<&str> = text.lines().collect();
+    let end = lines.len().saturating_sub(tail);
+    let mut kept = lines.get(head..end).unwrap_or_default().join("\n");
+    if !kept.is_empty() {
+        kept.push('\n');
+    }
+    kept
+}
+
+/// Keeps the part of a text from `start` to `end` of the way through, going by characters
+pub fn keep_fraction(text: &str, start: f64, end: f64) -> String {
+    let length = text.chars().count();
+    let (start, end) = ((length as f64 * start).round() as usize, (length as f64 * end).round() as usize);
+    text.chars().skip(start).take(end - start).collect()
+}
+
+/// Names of the ways files are normalized with --try-all-normalizations,
+/// the first is however the file was loaded
+pub const VARIANTS: &[&str] = &["as loaded", "trimmed", "no comments", "masked"];
+
+/// Normalizes a file every other way in `VARIANTS`, guessing the language from its extension
+pub fn variants(path: &Path, text: &str) -> Vec<String> {
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let (style, language) = match extension.as_deref() {
+        Some("py" | "pyw") => (CommentStyle::Hash, MaskLanguage::Python),
+        Some("java") => (CommentStyle::C, MaskLanguage::Java),
+        Some("js" | "mjs" | "ts") => (CommentStyle::C, MaskLanguage::JavaScript),
+        _ => (CommentStyle::C, MaskLanguage::C),
+    };
+    let without_comments = strip_comments(style, text);
+    let masked = mask_identifiers(language, &without_comments);
+    vec![text.chars().filter(|c| !c.is_whitespace()).collect(), without_comments, masked]
+}
+
+#[cfg(test)]
+#[test]
+fn comments_are_stripped() {
+    assert_eq!(strip_comments(CommentStyle::Hash, "x = '#' # hi\ny\n"), "x = '#' \ny\n");
+    assert_eq!(strip_comments(CommentStyle::C, "a /* b */ c // \"d\"\ne"), "a   c \ne");
+}
+
+#[cfg(test)]
+#[test]
+fn imports_are_stripped() {
+    let python = "import os\nfrom a.b import (\n    c,\n)\nimportant = 1\n";
+    assert_eq!(strip_imports(MaskLanguage::Python, python), "\n\n\n\nimportant = 1\n");
+    let c = "#include <stdio.h>\nusing namespace std;\nusing T = int;\n";
+    assert_eq!(strip_imports(MaskLanguage::C, c), "\n\nusing T = int;\n");
+    let js = "import {\n  a,\n} from './a';\nconst fs = require('fs');\nimported();\n";
+    assert_eq!(strip_imports(MaskLanguage::JavaScript, js), "\n\n\n\nimported();\n");
+}
+
+#[cfg(test)]
+#[test]
+fn numbers_are_masked() {
+    assert_eq!(mask_numbers("x1 = 42 + 0xFF * 1.5e-3f - 10L"), "x1 = 0 + 0 * 0 - 0");
+    assert_eq!(mask_numbers("a[3].b(2, v2)"), "a[0].b(0, v2)");
+}
@@ -0,0 +1,776 @@
+//! Reports and custom formats for the flagged pairs.
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What the scores mean when they're read from the options or shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// 1 means identical files
+    Similarity,
+    /// 1 - similarity, so 0 means identical files
+    Distance,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "similarity" => Ok(Metric::Similarity),
+            "distance" => Ok(Metric::Distance),
+            _ => Err(format!("unknown metric \"{}\", expected similarity or distance", s)),
+        }
+    }
+}
+
+impl Metric {
+    /// Turns a similarity into this metric. Also turns it back, since 1 - (1 - x) = x.
+    pub fn convert(self, score: f64) -> f64 {
+        match self {
+            Metric::Similarity => score,
+            Metric::Distance => 1.0 - score,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Similarity => "similarity",
+            Metric::Distance => "distance",
+        }
+    }
+
+    /// How a flagged score compares to the threshold
+    pub fn bound(self) -> &'static str {
+        match self {
+            Metric::Similarity => "at least",
+            Metric::Distance => "at most",
+        }
+    }
+}
+
+/// What order pairs are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Highest score first, as shown
+    ScoreDesc,
+    ScoreAsc,
+    /// By file name, so each file's pairs end up next to each other
+    File,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "score-desc" => Ok(SortOrder::ScoreDesc),
+            "score-asc" => Ok(SortOrder::ScoreAsc),
+            "file" => Ok(SortOrder::File),
+            _ => Err(format!("unknown sort order \"{}\", expected score-desc, score-asc or file", s)),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Most similar first, which is how things are sorted unless told otherwise
+    pub fn similar_first(metric: Metric) -> Self {
+        match metric {
+            Metric::Similarity => SortOrder::ScoreDesc,
+            Metric::Distance => SortOrder::ScoreAsc,
+        }
+    }
+
+    /// Compares two pairs, `score` being a similarity. Ties go by file name.
+    pub fn compare(self, metric: Metric, a: (&Path, &Path, f64), b: (&Path, &Path, f64)) -> Ordering {
+        // a pair's files in name order, so either one can be the first
+        fn names<'a>((x, y, _): (&'a Path, &'a Path, f64)) -> (&'a Path, &'a Path) {
+            if x <= y {
+                (x, y)
+            } else {
+                (y, x)
+            }
+        }
+        let by_score = || {
+            metric
+                .convert(a.2)
+                .partial_cmp(&metric.convert(b.2))
+                .expect("Couldn't compare two scores")
+        };
+        match self {
+            SortOrder::ScoreDesc => by_score().reverse().then_with(|| names(a).cmp(&names(b))),
+            SortOrder::ScoreAsc => by_score().then_with(|| names(a).cmp(&names(b))),
+            SortOrder::File => names(a).cmp(&names(b)).then_with(|| by_score().reverse()),
+        }
+    }
+}
+
+/// How flagged pairs are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStyle {
+    Plain,
+    /// A table with each file's group next to it, most similar first
+    Grouped,
+}
+
+impl FromStr for OutputStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(OutputStyle::Plain),
+            "grouped" => Ok(OutputStyle::Grouped),
+            _ => Err(format!("unknown output style \"{}\", expected plain or grouped", s)),
+        }
+    }
+}
+
+/// How paths are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Relative to where cheat_checker was run from, to paste into other commands
+    Cwd,
+    Absolute,
+    /// Relative to the deepest directory all the files are in
+    Common,
+}
+
+impl FromStr for PathStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cwd" => Ok(PathStyle::Cwd),
+            "absolute" => Ok(PathStyle::Absolute),
+            "common" => Ok(PathStyle::Common),
+            _ => Err(format!("unknown path style \"{}\", expected cwd, absolute or common", s)),
+        }
+    }
+}
+
+/// How the logfile is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Csv,
+    Moss,
+    /// csv with a header and clickable links, for pasting into a spreadsheet
+    SheetCsv,
+    /// An array of objects, like --serve's /results.json
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(LogFormat::Csv),
+            "moss" => Ok(LogFormat::Moss),
+            "sheet-csv" => Ok(LogFormat::SheetCsv),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown format \"{}\", expected csv, moss, sheet-csv or json", s)),
+        }
+    }
+}
+
+/// Quotes and escapes text as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// More about a pair for --json-details, only what the other options worked out
+pub struct PairDetails {
+    /// Characters in each file, after normalizing
+    pub lengths: (usize, usize),
+    /// Matching lines, with --show-ranges
+    pub ranges: Option<Vec<(RangeInclusive<usize>, RangeInclusive<usize>)>>,
+    /// The normalization that scored best, with --try-all-normalizations
+    pub variant: Option<&'static str>,
+}
+
+/// Writes pairs as a JSON array of objects, with `details` for each pair if there are any.
+///
+/// Pairs are the same as for `write_moss`.
+pub fn write_json(
+    out: &mut impl Write,
+    pairs: &[(&Path, String, &Path, String, f64)],
+    details: Option<&[PairDetails]>,
+    metric: Metric,
+) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, (_, x, _, y, score)) in pairs.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(
+            out,
+            "{{\"score\":{:.6},\"a\":{},\"b\":{}",
+            metric.convert(*score),
+            json_string(x),
+            json_string(y)
+        )?;
+        if let Some(details) = details.map(|details| &details[i]) {
+            write!(
+                out,
+                ",\"distance\":{:.6},\"length_a\":{},\"length_b\":{}",
+                1.0 - score,
+                details.lengths.0,
+                details.lengths.1
+            )?;
+            if let Some(ranges) = &details.ranges {
+                let ranges: Vec<String> = ranges
+                    .iter()
+                    .map(|(a, b)| format!("{{\"a\":[{},{}],\"b\":[{},{}]}}", a.start(), a.end(), b.start(), b.end()))
+                    .collect();
+                write!(out, ",\"ranges\":[{}]", ranges.join(","))?;
+            }
+            if let Some(variant) = details.variant {
+                write!(out, ",\"normalization\":{}", json_string(variant))?;
+            }
+        }
+        write!(out, "}}")?;
+    }
+    writeln!(out, "]")
+}
+
+/// What's written where the pairs would've been when nothing was flagged. Only json
+/// needs anything, so whatever reads it still gets valid JSON
+pub fn write_nothing_flagged(out: &mut impl Write, format: LogFormat) -> io::Result<()> {
+    match format {
+        LogFormat::Json => write_json(out, &[], None, Metric::Similarity),
+        LogFormat::Csv | LogFormat::Moss | LogFormat::SheetCsv => Ok(()),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn nothing_flagged_output() {
+    let written = |format: LogFormat| {
+        let mut out = Vec::new();
+        write_nothing_flagged(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(written(LogFormat::Json), "[]\n");
+    assert_eq!(written(LogFormat::Csv), "");
+    assert_eq!(written(LogFormat::Moss), "");
+}
+
+#[cfg(test)]
+#[test]
+fn json_output() {
+    let json = |pairs: &[(&Path, String, &Path, String, f64)], details: Option<&[PairDetails]>| {
+        let mut out = Vec::new();
+        write_json(&mut out, pairs, details, Metric::Similarity).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(json(&[], None), "[]\n");
+    assert_eq!(json_string(r#"say "hi"\n"#), r#""say \"hi\"\\n""#);
+    assert_eq!(json_string("a\tb\u{1}"), r#""a\tb\u0001""#);
+    let (a, b) = (Path::new(r#"C:\a "1".py"#), Path::new("b.py"));
+    let pairs = [
+        (a, a.display().to_string(), b, b.display().to_string(), 0.5),
+        (b, b.display().to_string(), a, a.display().to_string(), 0.25),
+    ];
+    assert_eq!(
+        json(&pairs, None),
+        concat!(
+            r#"[{"score":0.500000,"a":"C:\\a \"1\".py","b":"b.py"},"#,
+            r#"{"score":0.250000,"a":"b.py","b":"C:\\a \"1\".py"}]"#,
+            "\n"
+        )
+    );
+    let details = [
+        PairDetails { lengths: (3, 4), ranges: Some(vec![(1..=2, 3..=4)]), variant: Some("plain") },
+        PairDetails { lengths: (4, 3), ranges: None, variant: None },
+    ];
+    assert_eq!(
+        json(&pairs[..1], Some(&details[..1])),
+        concat!(
+            r#"[{"score":0.500000,"a":"C:\\a \"1\".py","b":"b.py","distance":0.500000,"length_a":3,"length_b":4,"#,
+            r#""ranges":[{"a":[1,2],"b":[3,4]}],"normalization":"plain"}]"#,
+            "\n"
+        )
+    );
+}
+
+/// What --summary-json writes, about the whole run
+pub struct Summary<'a> {
+    pub files: usize,
+    pub compared: usize,
+    /// Whether every pair was compared, instead of stopping early
+    pub complete: bool,
+    pub metric: Metric,
+    /// As it's shown, not necessarily a similarity
+    pub sensitivity: f64,
+    /// Every flagged pair, most similar first, shown as they'll be written
+    pub flagged: &'a [(String, String, f64)],
+    /// How long each phase took, like --timings shows
+    pub seconds: &'a [(&'static str, f64)],
+}
+
+/// How many of the flagged pairs are listed in the summary
+const SUMMARY_TOP_PAIRS: usize = 10;
+
+/// Writes a summary of the run as one JSON object
+pub fn write_summary(out: &mut impl Write, summary: &Summary) -> io::Result<()> {
+    let seconds: Vec<String> = summary
+        .seconds
+        .iter()
+        .map(|(phase, seconds)| format!("{}:{:.3}", json_string(phase), seconds))
+        .collect();
+    let top: Vec<String> = summary
+        .flagged
+        .iter()
+        .take(SUMMARY_TOP_PAIRS)
+        .map(|(x, y, score)| {
+            format!(
+                "{{\"score\":{:.6},\"a\":{},\"b\":{}}}",
+                summary.metric.convert(*score),
+                json_string(x),
+                json_string(y)
+            )
+        })
+        .collect();
+    writeln!(
+        out,
+        "{{\"files\":{},\"compared\":{},\"flagged\":{},\"complete\":{},\"metric\":{},\"sensitivity\":{},\
+         \"seconds\":{{{}}},\"top\":[{}]}}",
+        summary.files,
+        summary.compared,
+        summary.flagged.len(),
+        summary.complete,
+        json_string(summary.metric.name()),
+        // a percentile of no scores at all
+        if summary.sensitivity.is_finite() { summary.sensitivity.to_string() } else { "null".to_string() },
+        seconds.join(","),
+        top.join(",")
+    )
+}
+
+/// What ran and what it found, for --manifest
+pub struct Manifest<'a> {
+    pub version: &'static str,
+    /// What it was run with, as given
+    pub arguments: Vec<String>,
+    /// Every option, after defaults and environment variables
+    pub options: String,
+    pub cwd: String,
+    /// Each file and the hash of its contents, if it could still be read
+    pub inputs: &'a [(String, Option<String>)],
+    pub compared: usize,
+    pub complete: bool,
+    pub metric: Metric,
+    /// As it's shown, not necessarily a similarity
+    pub sensitivity: f64,
+    /// Every flagged pair, most similar first
+    pub flagged: &'a [(String, String, f64)],
+}
+
+/// Hex SHA-256 of some bytes
+pub fn sha256(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Writes the manifest as one JSON object. Its last field, `sha256`, is the
+/// hash of the object written up to just before it, then closed with `}`.
+pub fn write_manifest(out: &mut impl Write, manifest: &Manifest) -> io::Result<()> {
+    let arguments: Vec<String> = manifest.arguments.iter().map(|arg| json_string(arg)).collect();
+    let inputs: Vec<String> = manifest
+        .inputs
+        .iter()
+        .map(|(path, hash)| {
+            let hash = hash.as_deref().map_or("null".to_string(), json_string);
+            format!("{{\"path\":{},\"sha256\":{}}}", json_string(path), hash)
+        })
+        .collect();
+    let flagged: Vec<String> = manifest
+        .flagged
+        .iter()
+        .map(|(x, y, score)| {
+            format!(
+                "{{\"score\":{:.6},\"a\":{},\"b\":{}}}",
+                manifest.metric.convert(*score),
+                json_string(x),
+                json_string(y)
+            )
+        })
+        .collect();
+    let body = format!(
+        "{{\"version\":{},\"arguments\":[{}],\"options\":{},\"cwd\":{},\"inputs\":[{}],\"compared\":{},\
+         \"complete\":{},\"metric\":{},\"sensitivity\":{},\"flagged\":[{}]",
+        json_string(manifest.version),
+        arguments.join(","),
+        json_string(&manifest.options),
+        json_string(&manifest.cwd),
+        inputs.join(","),
+        manifest.compared,
+        manifest.complete,
+        json_string(manifest.metric.name()),
+        if manifest.sensitivity.is_finite() { manifest.sensitivity.to_string() } else { "null".to_string() },
+        flagged.join(",")
+    );
+    let digest = sha256(format!("{}}}", body).as_bytes());
+    writeln!(out, "{},\"sha256\":{}}}", body, json_string(&digest))
+}
+
+/// Writes each file's originality for --originality, with a header. Ones
+/// that weren't compared to anything get an empty column.
+pub fn write_originality(out: &mut impl Write, rows: &[(String, Option<f64>)]) -> io::Result<()> {
+    writeln!(out, "path,originality")?;
+    for (path, originality) in rows {
+        match originality {
+            Some(originality) => writeln!(out, "{},{:.6}", path, originality)?,
+            None => writeln!(out, "{},", path)?,
+        }
+    }
+    out.flush()
+}
+
+/// Escapes text for use in XML attributes and text
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes a JUnit XML report where every flagged pair is a failed testcase.
+///
+/// Scores are similarities, they're shown (and the sensitivity is given) in `metric`.
+///
+/// If nothing was flagged there's a single passing testcase instead, since
+/// some CI systems treat a report with no tests as an error.
+pub fn write_junit(
+    out: &mut impl Write,
+    flagged: &[(String, String, f64)],
+    sensitivity: f64,
+    metric: Metric,
+) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="cheat_checker" tests="{}" failures="{}">"#,
+        flagged.len().max(1),
+        flagged.len()
+    )?;
+    if flagged.is_empty() {
+        writeln!(
+            out,
+            r#"  <testcase classname="cheat_checker" name="no pairs with a {} {} {}"/>"#,
+            metric.name(),
+            metric.bound(),
+            sensitivity
+        )?;
+    }
+    for (x, y, score) in flagged {
+        let name = xml_escape(&format!("{} vs {}", x, y));
+        writeln!(out, r#"  <testcase classname="cheat_checker" name="{}">"#, name)?;
+        writeln!(
+            out,
+            r#"    <failure message="{} {:.6} is {} {}">{}</failure>"#,
+            metric.name(),
+            metric.convert(*score),
+            metric.bound(),
+            sensitivity,
+            name
+        )?;
+        writeln!(out, "  </testcase>")?;
+    }
+    writeln!(out, "</testsuite>")
+}
+
+/// Writes pairs as an HTML table laid out like a MOSS results page.
+///
+/// Each pair is the full path (for the link) and the path to show of both
+/// files, and their similarity.
+pub fn write_moss(
+    out: &mut impl Write,
+    pairs: &[(&std::path::Path, String, &std::path::Path, String, f64)],
+) -> io::Result<()> {
+    writeln!(out, "<HTML>\n<HEAD>\n<TITLE>Moss Results</TITLE>\n</HEAD>\n<BODY>")?;
+    writeln!(out, "Moss Results<p>\ncheat_checker, {} pairs<p>\n<HR>", pairs.len())?;
+    writeln!(out, "<TABLE>\n<TR><TH>File 1<TH>File 2<TH>Similarity")?;
+    for (x, x_name, y, y_name, score) in pairs {
+        let percent = (score * 100.0).round();
+        let link = |path: &std::path::Path| xml_escape(&file_url(path));
+        writeln!(out, "<TR><TD><A HREF=\"{}\">{} ({}%)</A>", link(x), xml_escape(x_name), percent)?;
+        writeln!(out, "    <TD><A HREF=\"{}\">{} ({}%)</A>", link(y), xml_escape(y_name), percent)?;
+        writeln!(out, "<TD ALIGN=right>{:.6}", score)?;
+    }
+    writeln!(out, "</TABLE>\n<HR>\n</BODY>\n</HTML>")
+}
+
+/// A `file://` URL for an absolute path, with anything that isn't allowed in a URL escaped
+pub fn file_url(path: &std::path::Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => url.push(byte as char),
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// Writes the pairs as csv for a spreadsheet, `score,file_a,file_b` with a header.
+///
+/// Each file is a HYPERLINK formula, so the sheet shows its name and opens it when clicked.
+pub fn write_sheet_csv(
+    out: &mut impl Write,
+    pairs: &[(&std::path::Path, String, &std::path::Path, String, f64)],
+) -> io::Result<()> {
+    // quotes are doubled once for the formula's string, then again for the csv
+    let link = |path: &std::path::Path, name: &str| {
+        let formula = format!("=HYPERLINK(\"{}\",\"{}\")", file_url(path), name.replace('"', "\"\""));
+        format!("\"{}\"", formula.replace('"', "\"\""))
+    };
+    writeln!(out, "score,file_a,file_b")?;
+    for (x, x_name, y, y_name, score) in pairs {
+        writeln!(out, "{:.6},{},{}", score, link(x, x_name), link(y, y_name))?;
+    }
+    Ok(())
+}
+
+/// How many bars the histogram has, each covers 1/BUCKETS of the scores
+const BUCKETS: usize = 20;
+
+/// How many scores fall in each bar of the histogram, counted as they come in
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [usize; BUCKETS],
+}
+
+impl Histogram {
+    pub fn add(&mut self, score: f64) {
+        let bucket = (score.clamp(0.0, 1.0) * BUCKETS as f64) as usize;
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+    }
+}
+
+/// Draws how many scores fall in each range as a bar chart, fitting in `width` columns
+pub fn write_histogram(out: &mut impl Write, histogram: &Histogram, width: usize) -> io::Result<()> {
+    let counts = &histogram.counts;
+    let most = counts.iter().copied().max().unwrap_or(0).max(1);
+    let count_width = most.to_string().len();
+    // room for the range and count before the bar
+    let bar_width = width.saturating_sub(12 + count_width).max(10);
+    for (bucket, &count) in counts.iter().enumerate().rev() {
+        let start = bucket as f64 / BUCKETS as f64;
+        // round up anything nonzero, so a single pair still shows
+        let length = (count * bar_width).div_ceil(most);
+        writeln!(
+            out,
+            "{:.2}-{:.2} {:>count_width$} {}",
+            start,
+            start + 1.0 / BUCKETS as f64,
+            count,
+            "\u{2588}".repeat(length),
+            count_width = count_width
+        )?;
+    }
+    Ok(())
+}
+
+/// Something that can appear between braces in an output template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Score,
+    Distance,
+    A,
+    B,
+    GroupA,
+    GroupB,
+    Weight,
+    Baseline,
+}
+
+impl Placeholder {
+    const ALL: &'static [(&'static str, Placeholder)] = &[
+        ("score", Placeholder::Score),
+        ("distance", Placeholder::Distance),
+        ("a", Placeholder::A),
+        ("b", Placeholder::B),
+        ("group_a", Placeholder::GroupA),
+        ("group_b", Placeholder::GroupB),
+        ("weight", Placeholder::Weight),
+        ("baseline", Placeholder::Baseline),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(Placeholder),
+}
+
+/// A user-given line format for flagged pairs, like `{score} {a} {b}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// Everything a template can show about a pair
+pub struct PairFields<'a> {
+    pub score: f64,
+    pub a: &'a str,
+    pub b: &'a str,
+    pub group_a: &'a str,
+    pub group_b: &'a str,
+    pub weight: f64,
+    /// Label of the baseline one of the files is from, if any
+    pub baseline: &'a str,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                // doubled braces are literal braces
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let Some(&(_, field)) = Placeholder::ALL.iter().find(|(n, _)| *n == name) else {
+                        let known: Vec<_> = Placeholder::ALL.iter().map(|(n, _)| *n).collect();
+                        return Err(format!("unknown placeholder {{{}}}, expected one of {}", name, known.join(", ")));
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err("unmatched }, use }} for a literal brace".to_string()),
+                // shells make it awkward to pass real tabs and newlines
+                '\\' if chars.peek() == Some(&'t') => {
+                    chars.next();
+                    literal.push('\t');
+                }
+                '\\' if chars.peek() == Some(&'n') => {
+                    chars.next();
+                    literal.push('\n');
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    /// Fills in the template for one pair
+    pub fn render(&self, pair: &PairFields) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => line.push_str(text),
+                Part::Field(Placeholder::Score) => line.push_str(&format!("{:.6}", pair.score)),
+                Part::Field(Placeholder::Distance) => line.push_str(&format!("{:.6}", 1.0 - pair.score)),
+                Part::Field(Placeholder::A) => line.push_str(pair.a),
+                Part::Field(Placeholder::B) => line.push_str(pair.b),
+                Part::Field(Placeholder::GroupA) => line.push_str(pair.group_a),
+                Part::Field(Placeholder::GroupB) => line.push_str(pair.group_b),
+                Part::Field(Placeholder::Weight) => line.push_str(&pair.weight.to_string()),
+                Part::Field(Placeholder::Baseline) => line.push_str(pair.baseline),
+            }
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn template_placeholders() {
+    let template: Template = "{score}\\t{a} {{{b}}}".parse().unwrap();
+    let pair = PairFields { score: 0.5, a: "x", b: "y", group_a: "", group_b: "", weight: 1.0, baseline: "" };
+    assert_eq!(template.render(&pair), "0.500000\tx {y}");
+    assert!("{nope}".parse::<Template>().is_err());
+    assert!("}".parse::<Template>().is_err());
+}
+
+/// Splits the shown pairs into pages, for --page
+pub struct Pager {
+    size: usize,
+    total: usize,
+    shown: usize,
+    /// Waits for enter between pages instead of just printing a separator
+    interactive: bool,
+    stopped: bool,
+}
+
+impl Pager {
+    pub fn new(size: usize, total: usize, interactive: bool) -> Self {
+        Pager {
+            size,
+            total,
+            shown: 0,
+            interactive,
+            stopped: false,
+        }
+    }
+
+    /// Call before showing each pair, false once they've asked to stop
+    pub fn next(&mut self, out: &mut dyn Write) -> bool {
+        if self.stopped {
+            return false;
+        }
+        if self.shown > 0 && self.shown.is_multiple_of(self.size) {
+            let page = self.shown / self.size + 1;
+            let pages = self.total.div_ceil(self.size);
+            if self.interactive {
+                let _ = out.flush();
+                eprint!("-- {} of {} shown, enter for page {}/{}, q to stop -- ", self.shown, self.total, page, pages);
+                let mut answer = String::new();
+                // nothing more to read means nobody's there to ask
+                let read = io::stdin().read_line(&mut answer).unwrap_or(0);
+                if read == 0 || answer.trim().eq_ignore_ascii_case("q") {
+                    self.stopped = true;
+                    return false;
+                }
+            } else {
+                let _ = writeln!(out, "-- page {}/{} --", page, pages);
+            }
+        }
+        self.shown += 1;
+        true
+    }
+}
@@ -0,0 +1,160 @@
+//! Named sets of normalization steps, picked for each file by its extension.
+//!
+//! Profiles file format, one profile per line:
+//!
+//! ```text
+//! # NAME  EXTENSIONS  STEPS
+//! kotlin  kt,kts      line-endings,strip-comments=c,trim
+//! ```
+//!
+//! Steps are `line-endings`, `strip-comments=STYLE`, `mask-identifiers=LANG`,
+//! `keep-indent` and `trim`, and run in the order they're given.
+use crate::normalize::{self, CommentStyle, MaskLanguage};
+use anyhow::Context;
+use std::path::Path;
+use std::str::FromStr;
+
+/// One thing a profile does to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    LineEndings,
+    StripComments(CommentStyle),
+    MaskIdentifiers(MaskLanguage),
+    KeepIndent,
+    Trim,
+}
+
+impl FromStr for Step {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "line-endings" => Ok(Step::LineEndings),
+            None if s == "keep-indent" => Ok(Step::KeepIndent),
+            None if s == "trim" => Ok(Step::Trim),
+            Some(("strip-comments", style)) => Ok(Step::StripComments(style.parse()?)),
+            Some(("mask-identifiers", language)) => Ok(Step::MaskIdentifiers(language.parse()?)),
+            _ => Err(format!(
+                "unknown step \"{}\", expected line-endings, strip-comments=STYLE, \
+                 mask-identifiers=LANG, keep-indent or trim",
+                s
+            )),
+        }
+    }
+}
+
+/// Normalization steps for the files with some extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    extensions: Vec<String>,
+    steps: Vec<Step>,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [name, extensions, steps] = fields[..] else {
+            return Err(format!("expected NAME EXTENSIONS STEPS, got \"{}\"", s));
+        };
+        Ok(Profile {
+            name: name.to_string(),
+            extensions: extensions
+                .split(',')
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            steps: steps.split(',').map(str::parse).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Profiles that are always available
+const BUILT_IN: &[&str] = &[
+    "python py,pyw line-endings,strip-comments=hash,mask-identifiers=python,keep-indent",
+    "c c,h,cpp,hpp,cc,cxx line-endings,strip-comments=c,mask-identifiers=c,trim",
+    "java java line-endings,strip-comments=c,mask-identifiers=java,trim",
+    "js js,mjs,cjs,ts line-endings,strip-comments=c,mask-identifiers=js,trim",
+];
+
+/// Reads the built-in profiles, then any from a profiles file, which can replace the built-in ones
+pub fn available(file: Option<&Path>) -> anyhow::Result<Vec<Profile>> {
+    let mut profiles: Vec<Profile> = BUILT_IN
+        .iter()
+        .map(|line| line.parse().expect("built-in profiles are valid"))
+        .collect();
+    let Some(file) = file else {
+        return Ok(profiles);
+    };
+    let text = std::fs::read_to_string(file)
+        .with_context(|| format!("couldn't read {}", file.to_string_lossy()))?;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let profile: Profile = line
+            .parse()
+            .map_err(|err| anyhow::anyhow!("{}:{}: {}", file.to_string_lossy(), number + 1, err))?;
+        profiles.retain(|existing| existing.name != profile.name);
+        profiles.push(profile);
+    }
+    Ok(profiles)
+}
+
+/// Finds the first of the chosen profiles that handles a file's extension
+pub fn for_path<'a>(profiles: &'a [Profile], path: &Path) -> Option<&'a Profile> {
+    // compressed files go by the extension they had before being compressed
+    let extension = |path: &Path| path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let mut ext = extension(path)?;
+    if matches!(ext.as_str(), "gz" | "bz2" | "xz") {
+        ext = extension(Path::new(path.file_stem()?))?;
+    }
+    profiles.iter().find(|profile| profile.extensions.contains(&ext))
+}
+
+impl Profile {
+    pub fn apply(&self, text: String, tab_width: usize) -> String {
+        self.steps.iter().fold(text, |text, step| match *step {
+            Step::LineEndings => normalize::normalize_line_endings(&text),
+            Step::StripComments(style) => normalize::strip_comments(style, &text),
+            Step::MaskIdentifiers(language) => normalize::mask_identifiers(language, &text),
+            Step::KeepIndent => normalize::normalize_indentation(&text, tab_width),
+            Step::Trim => text.chars().filter(|c| !c.is_whitespace()).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn profiles_file_is_read() {
+    let file = std::env::temp_dir().join(format!("cheat_checker_profiles_{}.txt", std::process::id()));
+    let text = "# NAME  EXTENSIONS  STEPS\n\nkotlin  .KT,kts  line-endings,strip-comments=c,trim\npython py trim\n";
+    std::fs::write(&file, text).unwrap();
+    let profiles = available(Some(&file)).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    let kotlin = for_path(&profiles, Path::new("a/Main.kt.gz")).unwrap();
+    assert_eq!(kotlin.name, "kotlin");
+    assert_eq!(kotlin.steps, [Step::LineEndings, Step::StripComments(CommentStyle::C), Step::Trim]);
+    assert_eq!(kotlin.apply("a = 1 // one\r\n".to_string(), 4), "a=1");
+    // the file's python replaces the built-in one
+    assert_eq!(profiles.iter().filter(|profile| profile.name == "python").count(), 1);
+    assert_eq!(for_path(&profiles, Path::new("x.py")).unwrap().steps, [Step::Trim]);
+    assert!(for_path(&profiles, Path::new("README")).is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn bad_profiles_are_errors() {
+    assert!("kotlin kt".parse::<Profile>().unwrap_err().contains("expected NAME EXTENSIONS STEPS"));
+    assert!("kotlin kt trim extra".parse::<Profile>().is_err());
+    assert!("kotlin kt trim,sparkle".parse::<Profile>().unwrap_err().contains("unknown step \"sparkle\""));
+    assert!("kotlin kt strip-comments=nope".parse::<Profile>().is_err());
+    // and the line it's on is in the error
+    let file = std::env::temp_dir().join(format!("cheat_checker_bad_profiles_{}.txt", std::process::id()));
+    std::fs::write(&file, "# fine\nkotlin kt trim\nswift swift\n").unwrap();
+    let err = available(Some(&file)).unwrap_err().to_string();
+    std::fs::remove_file(&file).unwrap();
+    assert!(err.ends_with(":3: expected NAME EXTENSIONS STEPS, got \"swift swift\""), "{}", err);
+}
@@ -0,0 +1,67 @@
+//! `import cheat_checker` from Python, built with the pyo3 feature, see the README.
+//!
+//! Only comparing is shared with the command line. Files are read as UTF-8 and
+//! compared as they are, like the command line does without any normalizing options.
+use crate::compare::{compare_all, Comparing};
+use crate::{similarity, split};
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+
+/// Compares every pair of files, giving back `(file, file, similarity)` for the
+/// ones that are at least `sensitivity` similar, most similar first
+#[pyfunction]
+// named so it doesn't clash with crate::compare
+#[pyo3(name = "compare", signature = (paths, sensitivity, algorithm = "levenshtein", jobs = None))]
+fn compare_files(
+    py: Python<'_>,
+    paths: Vec<PathBuf>,
+    sensitivity: f64,
+    algorithm: &str,
+    jobs: Option<usize>,
+) -> PyResult<Vec<(String, String, f64)>> {
+    let algorithm: similarity::Algorithm = algorithm.parse().map_err(PyValueError::new_err)?;
+    let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+    let mut texts = HashMap::new();
+    for path in paths {
+        let bytes = std::fs::read(&path)
+            .map_err(|err| PyOSError::new_err(format!("Couldn't read {}. ({})", path.to_string_lossy(), err)))?;
+        let mut text = String::from_utf8_lossy(&bytes).into_owned();
+        // the command line does this for it too, whatever the options
+        if let similarity::Algorithm::SortedBlocks(language) = algorithm {
+            text = split::sorted_blocks(language, &text);
+        }
+        texts.insert(path, text);
+    }
+    // python can get on with other threads while this runs
+    let mut flagged = py.detach(|| {
+        let scorer = algorithm.scorer();
+        let files: HashMap<PathBuf, similarity::PreparedFile> =
+            texts.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+        let workqueue: Vec<(&PathBuf, &PathBuf)> =
+            files.keys().flat_map(|x| files.keys().filter(move |y| x < *y).map(move |y| (x, y))).collect();
+        // nothing wants the scores of pairs that can't be flagged
+        let comparing = Comparing {
+            skip_below: Some(sensitivity),
+            ..Comparing::new(algorithm, similarity::count_rarity(algorithm, files.values()))
+        };
+        let mut flagged = Vec::new();
+        compare_all(workqueue, &files, &comparing, jobs, &AtomicBool::new(false), |x, y, score| {
+            if score >= sensitivity {
+                flagged.push((x.to_string_lossy().into_owned(), y.to_string_lossy().into_owned(), score));
+            }
+        });
+        flagged
+    });
+    flagged.sort_by(|a, b| b.2.total_cmp(&a.2).then_with(|| (&a.0, &a.1).cmp(&(&b.0, &b.1))));
+    Ok(flagged)
+}
+
+#[pymodule]
+fn cheat_checker(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(compare_files, module)?)
+}
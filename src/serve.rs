@@ -0,0 +1,69 @@
+//! Serves the results to a browser on this machine, for looking through them
+//! without writing any files.
+//!
+//! `/` is the same page as `--format moss`, and `/results.json` has the same
+//! pairs as JSON.
+use crate::output::{self, Metric, PairDetails};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How often to check for ctrl-c while there's nobody to answer
+const POLL_TIME: Duration = Duration::from_millis(100);
+
+/// Answers requests until `stop` gets set
+pub fn serve(
+    port: u16,
+    pairs: &[(&Path, String, &Path, String, f64)],
+    details: Option<&[PairDetails]>,
+    metric: Metric,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut html = Vec::new();
+    output::write_moss(&mut html, pairs)?;
+    let mut json = Vec::new();
+    output::write_json(&mut json, pairs, details, metric)?;
+
+    // only this machine, the paths in there might be private
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    listener.set_nonblocking(true)?;
+    log::info!("Serving the results at http://{}/, ctrl-c to stop.", listener.local_addr()?);
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(err) = respond(stream, &html, &json) {
+                    log::debug!("Couldn't answer a request. ({})", err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(POLL_TIME),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, html: &[u8], json: &[u8]) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // the request line is all that matters, and it comes first
+    let mut request = [0; 4096];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body): (_, _, &[u8]) = match path {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", html),
+        "/results.json" => ("200 OK", "application/json", json),
+        _ => ("404 Not Found", "text/plain", b"not found\n"),
+    };
+    log::debug!("{} {}", status, path);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
@@ -0,0 +1,809 @@
+//! The different ways of scoring how similar two files are.
+//!
+//! Every algorithm gives a score between 0 and 1, where 1 means identical,
+//! so the thresholds mean roughly the same thing whichever one is used.
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::split::SplitLanguage;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A file, plus whatever an algorithm worked out about it ahead of time
+pub struct PreparedFile<'a> {
+    pub text: &'a str,
+    /// Hashes of the pieces being compared, sorted and deduplicated for
+    /// the algorithms that compare sets
+    pub fingerprints: Vec<u64>,
+    /// The file normalized in other ways, the best scoring one counts
+    pub variants: Vec<PreparedFile<'a>>,
+    /// Pieces of the file that are also scored on their own, for --granular.
+    /// The best pair of pieces counts, if it beats the whole files.
+    pub parts: Vec<(Granularity, PreparedFile<'a>)>,
+}
+
+/// What a score was worked out over, for --granular
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    File,
+    Function,
+    /// A few lines in a row
+    Window,
+}
+
+impl Granularity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Granularity::File => "whole file",
+            Granularity::Function => "function",
+            Granularity::Window => "lines",
+        }
+    }
+}
+
+/// Scores pairs of files. Each thread gets its own, so it can keep buffers around.
+///
+/// `score` runs for every pair, so it shouldn't allocate. Anything it needs
+/// to work in belongs in the scorer, behind a `RefCell` since it's never shared.
+pub trait Similarity {
+    /// Works out anything needed about a file, once, before it's compared to anything
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        PreparedFile { text, fingerprints: Vec::new(), variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64;
+
+    /// The most `score` could come out as, worked out without comparing anything.
+    /// Pairs this is too low for don't need scoring at all.
+    fn upper_bound(&self, _a: &PreparedFile, _b: &PreparedFile) -> f64 {
+        1.0
+    }
+}
+
+/// Edit distance is at least the difference in length, so the lengths alone cap
+/// the similarity. Worked out the same way eddie does, so it's never below the real score.
+fn length_bound(a: usize, b: usize) -> f64 {
+    1.0 - a.abs_diff(b) as f64 / a.max(b).max(1) as f64
+}
+
+/// How long a file is for the slice algorithms, in whatever they're comparing
+fn unit_len(file: &PreparedFile, unit: Unit) -> usize {
+    match unit {
+        Unit::Graphemes => file.fingerprints.len(),
+        _ => file.text.len(),
+    }
+}
+
+/// Scores a pair, taking the best of their variants if they have any.
+///
+/// Also returns which variant that was, 0 being the files themselves.
+pub fn best_score(scorer: &dyn Similarity, a: &PreparedFile, b: &PreparedFile) -> (f64, usize) {
+    let mut best = (scorer.score(a, b), 0);
+    for (i, (a, b)) in a.variants.iter().zip(&b.variants).enumerate() {
+        let score = scorer.score(a, b);
+        if score > best.0 {
+            best = (score, i + 1);
+        }
+    }
+    best
+}
+
+/// Scores a pair like `best_score`, then every pair of their pieces that are
+/// the same kind, keeping the best. Also returns which kind that was.
+pub fn best_granular(scorer: &dyn Similarity, a: &PreparedFile, b: &PreparedFile) -> (f64, Granularity) {
+    let mut best = (best_score(scorer, a, b).0, Granularity::File);
+    for (kind, a) in &a.parts {
+        for (_, b) in b.parts.iter().filter(|(other, _)| other == kind) {
+            let score = scorer.score(a, b);
+            if score > best.0 {
+                best = (score, *kind);
+            }
+        }
+    }
+    best
+}
+
+/// Which algorithm to compare files with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Levenshtein(Unit),
+    /// Levenshtein, but swapping two neighbors is one edit instead of two.
+    /// Needs memory for every pair of characters, so it's only for smaller files.
+    Damerau(Unit),
+    Lines(Overlap),
+    /// Levenshtein, lines and the longest shared run of text, weighted together
+    Confidence(Weights),
+    /// Levenshtein over how each line is indented and how long it is,
+    /// ignoring what's actually on it
+    Structure,
+    /// Levenshtein, after each file's top-level blocks are sorted by what's in
+    /// them. The sorting happens while loading, see `split::sorted_blocks`.
+    SortedBlocks(SplitLanguage),
+    /// Runs of tokens that match another file's, allowing a few tokens to differ
+    FuzzyShingles(Shingling),
+    /// Shingles of tokens in both files, each weighted by how rare it is
+    /// across all the files, see `count_rarity`
+    TfIdf,
+    /// Jaccard over a sample of each file's hashed runs of tokens, picked by
+    /// winnowing like MOSS does
+    Winnowing,
+}
+
+/// How --algorithm fuzzy-shingles cuts files up and what counts as a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shingling {
+    /// How many tokens are in each shingle
+    pub size: usize,
+    /// How many of a shingle's tokens can be different and still match
+    pub edits: usize,
+}
+
+impl Shingling {
+    pub const DEFAULT: Shingling = Shingling { size: 5, edits: 1 };
+}
+
+impl Default for Shingling {
+    fn default() -> Self {
+        Shingling::DEFAULT
+    }
+}
+
+impl FromStr for Shingling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s
+            .split_once(',')
+            .and_then(|(size, edits)| Some((size.trim().parse().ok()?, edits.trim().parse().ok()?)));
+        match parsed {
+            Some((size, edits)) if size > 0 && edits < size => Ok(Shingling { size, edits }),
+            _ => Err(format!("expected a size and fewer edits than that like 5,1, got \"{}\"", s)),
+        }
+    }
+}
+
+/// How much each signal counts for in --algorithm confidence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weights {
+    pub edit: f64,
+    pub lines: f64,
+    /// The longest run of text both files have, out of the shorter file
+    pub substring: f64,
+}
+
+impl Weights {
+    pub const DEFAULT: Weights = Weights { edit: 0.5, lines: 0.3, substring: 0.2 };
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights::DEFAULT
+    }
+}
+
+impl FromStr for Weights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let weights: Vec<f64> = s
+            .split(',')
+            .map(|weight| weight.trim().parse().map_err(|_| format!("\"{}\" isn't a number", weight)))
+            .collect::<Result<_, _>>()?;
+        match weights[..] {
+            [edit, lines, substring] if weights.iter().all(|&weight| weight >= 0.0) && weights.iter().sum::<f64>() > 0.0 => {
+                Ok(Weights { edit, lines, substring })
+            }
+            _ => Err(format!("expected three weights that aren't negative like 0.5,0.3,0.2, got \"{}\"", s)),
+        }
+    }
+}
+
+/// How the algorithms that compare sets turn what's shared into a score
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// Shared over everything in either file
+    Jaccard,
+    /// How much of the smaller file is in the larger one, so a small file
+    /// copied into a big one still scores 1
+    Containment,
+}
+
+impl FromStr for Overlap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jaccard" => Ok(Overlap::Jaccard),
+            "containment" => Ok(Overlap::Containment),
+            _ => Err(format!("unknown overlap metric \"{}\", expected jaccard or containment", s)),
+        }
+    }
+}
+
+/// What counts as one character for edit distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Chars,
+    Bytes,
+    /// What a reader would call one character, like an emoji or a letter
+    /// with combining accents, even when it's several chars
+    Graphemes,
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chars" => Ok(Unit::Chars),
+            "bytes" => Ok(Unit::Bytes),
+            "graphemes" => Ok(Unit::Graphemes),
+            _ => Err(format!("unknown unit \"{}\", expected chars, bytes or graphemes", s)),
+        }
+    }
+}
+
+/// An algorithm that can be picked with --algorithm, and what --help-algorithms says about it
+pub struct Description {
+    pub name: &'static str,
+    /// What it is before --unit or --overlap-metric change it
+    pub algorithm: Algorithm,
+    pub summary: &'static str,
+    /// What moving code around does to the score
+    pub reordering: &'static str,
+    /// What renaming things does to the score
+    pub renaming: &'static str,
+    pub cost: &'static str,
+}
+
+/// Every algorithm, in the order they're listed
+pub const ALGORITHMS: &[Description] = &[
+    Description {
+        name: "levenshtein",
+        algorithm: Algorithm::Levenshtein(Unit::Chars),
+        summary: "edits needed to turn one file into the other",
+        reordering: "lowers it, moved code counts as deleted and added again",
+        renaming: "lowers it a little, by the characters changed",
+        cost: "quadratic in the file length, the default",
+    },
+    Description {
+        name: "damerau",
+        algorithm: Algorithm::Damerau(Unit::Chars),
+        summary: "levenshtein, but swapping two neighbors is one edit",
+        reordering: "lowers it, same as levenshtein",
+        renaming: "lowers it a little, by the characters changed",
+        cost: "slower than levenshtein and needs memory for every pair of characters",
+    },
+    Description {
+        name: "lines",
+        algorithm: Algorithm::Lines(Overlap::Jaccard),
+        summary: "share of distinct lines in both files",
+        reordering: "doesn't change it at all",
+        renaming: "lowers it a lot, every line with the name is different",
+        cost: "about linear, the fastest",
+    },
+    Description {
+        name: "confidence",
+        algorithm: Algorithm::Confidence(Weights::DEFAULT),
+        summary: "levenshtein, lines and the longest shared run of text weighted together, see --confidence-weights",
+        reordering: "lowers it some, lines and the shared run don't mind",
+        renaming: "lowers it some, mostly through lines",
+        cost: "about twice levenshtein",
+    },
+    Description {
+        name: "structure",
+        algorithm: Algorithm::Structure,
+        summary: "levenshtein over each line's indentation and rough length, coarse, best next to another algorithm",
+        reordering: "lowers it, same as levenshtein",
+        renaming: "barely changes it, unless names get a lot longer or shorter",
+        cost: "quadratic in the number of lines, fast",
+    },
+    Description {
+        name: "sorted-blocks",
+        algorithm: Algorithm::SortedBlocks(SplitLanguage::Python),
+        summary: "levenshtein after sorting each file's top-level blocks, see --block-language",
+        reordering: "doesn't change it when whole functions move, lowers it like levenshtein inside them",
+        renaming: "lowers it a little, but a renamed function can sort somewhere else",
+        cost: "same as levenshtein",
+    },
+    Description {
+        name: "fuzzy-shingles",
+        algorithm: Algorithm::FuzzyShingles(Shingling::DEFAULT),
+        summary: "share of runs of tokens with a close match in the other file, see --fuzzy-shingle",
+        reordering: "barely changes it, only the runs across the edges of what moved",
+        renaming: "lowers it a little, as long as runs keep enough tokens the same",
+        cost: "quadratic in the number of tokens, slower than levenshtein with more than 0 edits",
+    },
+    Description {
+        name: "tf-idf",
+        algorithm: Algorithm::TfIdf,
+        summary: "shared runs of tokens, where ones most files have count for less, like template code",
+        reordering: "barely changes it, only the runs across the edges of what moved",
+        renaming: "lowers it a lot, every run with the name in it is different",
+        cost: "about linear, after counting every file's runs once",
+    },
+    Description {
+        name: "winnowing",
+        algorithm: Algorithm::Winnowing,
+        summary: "share of fingerprints of runs of tokens in both files, like MOSS",
+        reordering: "barely changes it, only the runs across the edges of what moved",
+        renaming: "lowers it a lot, every run with the name in it is different",
+        cost: "about linear, good for big files",
+    },
+];
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s_lower = s.to_ascii_lowercase();
+        if let Some(description) = ALGORITHMS.iter().find(|description| description.name == s_lower) {
+            return Ok(description.algorithm);
+        }
+        let names: Vec<&str> = ALGORITHMS.iter().map(|description| description.name).collect();
+        let (last, rest) = names.split_last().expect("there's at least one algorithm");
+        Err(format!("unknown algorithm \"{}\", expected {} or {}", s, rest.join(", "), last))
+    }
+}
+
+impl Algorithm {
+    /// Same as `scorer_with`, but tf-idf weighs every shingle the same.
+    /// Fine for preparing files, which doesn't need the weights.
+    pub fn scorer(self) -> Box<dyn Similarity> {
+        self.scorer_with(None)
+    }
+
+    /// A scorer for this algorithm, with tf-idf weighing shingles by `rarity` if there is one
+    pub fn scorer_with(self, rarity: Option<Arc<Rarity>>) -> Box<dyn Similarity> {
+        match self {
+            Algorithm::Levenshtein(Unit::Chars) => Box::new(Levenshtein(eddie::str::Levenshtein::new())),
+            Algorithm::Levenshtein(unit) => Box::new(SliceLevenshtein(eddie::slice::Levenshtein::new(), unit)),
+            Algorithm::Damerau(Unit::Chars) => Box::new(Damerau(eddie::str::DamerauLevenshtein::new())),
+            Algorithm::Damerau(unit) => Box::new(SliceDamerau {
+                bytes: eddie::slice::DamerauLevenshtein::new(),
+                graphemes: eddie::slice::DamerauLevenshtein::new(),
+                unit,
+            }),
+            Algorithm::Lines(overlap) => Box::new(Lines(overlap)),
+            Algorithm::Confidence(weights) => Box::new(Confidence {
+                edit: eddie::str::Levenshtein::new(),
+                lines: Lines(Overlap::Jaccard),
+                row: RefCell::new(Vec::new()),
+                weights,
+            }),
+            Algorithm::Structure => Box::new(Structure(eddie::slice::Levenshtein::new())),
+            Algorithm::SortedBlocks(_) => Box::new(Levenshtein(eddie::str::Levenshtein::new())),
+            Algorithm::FuzzyShingles(shingling) => Box::new(FuzzyShingles(shingling)),
+            Algorithm::TfIdf => Box::new(TfIdf(rarity)),
+            Algorithm::Winnowing => Box::new(Winnowing),
+        }
+    }
+}
+
+/// Edit distance between the whole texts, scaled by the longer one's length
+struct Levenshtein(eddie::str::Levenshtein);
+
+impl Similarity for Levenshtein {
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        self.0.similarity(a.text, b.text)
+    }
+
+    fn upper_bound(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        length_bound(a.text.chars().count(), b.text.chars().count())
+    }
+}
+
+/// Edit distance over bytes or graphemes instead of chars
+struct SliceLevenshtein(eddie::slice::Levenshtein, Unit);
+
+/// Graphemes are different lengths, so they're compared by hash
+fn prepare_units(text: &str, unit: Unit) -> PreparedFile<'_> {
+    let fingerprints = match unit {
+        Unit::Graphemes => text
+            .graphemes(true)
+            .map(|grapheme| {
+                let mut hasher = DefaultHasher::new();
+                grapheme.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+}
+
+impl Similarity for SliceLevenshtein {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        prepare_units(text, self.1)
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        match self.1 {
+            Unit::Graphemes => self.0.similarity(&a.fingerprints, &b.fingerprints),
+            _ => self.0.similarity(a.text.as_bytes(), b.text.as_bytes()),
+        }
+    }
+
+    fn upper_bound(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        length_bound(unit_len(a, self.1), unit_len(b, self.1))
+    }
+}
+
+struct Damerau(eddie::str::DamerauLevenshtein);
+
+impl Similarity for Damerau {
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        self.0.similarity(a.text, b.text)
+    }
+
+    fn upper_bound(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        length_bound(a.text.chars().count(), b.text.chars().count())
+    }
+}
+
+/// Damerau over bytes or graphemes, which need different element types
+struct SliceDamerau {
+    bytes: eddie::slice::DamerauLevenshtein<u8>,
+    graphemes: eddie::slice::DamerauLevenshtein<u64>,
+    unit: Unit,
+}
+
+impl Similarity for SliceDamerau {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        prepare_units(text, self.unit)
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        match self.unit {
+            Unit::Graphemes => self.graphemes.similarity(&a.fingerprints, &b.fingerprints),
+            _ => self.bytes.similarity(a.text.as_bytes(), b.text.as_bytes()),
+        }
+    }
+
+    fn upper_bound(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        length_bound(unit_len(a, self.unit), unit_len(b, self.unit))
+    }
+}
+
+/// How many distinct lines the files share, out of all the distinct lines in either.
+///
+/// Doesn't care what order things are in, so moving functions around doesn't help.
+struct Lines(Overlap);
+
+impl Similarity for Lines {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        let mut fingerprints: Vec<u64> = text
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|words| !words.is_empty())
+            .map(|words| {
+                let mut hasher = DefaultHasher::new();
+                words.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        fingerprints.sort_unstable();
+        fingerprints.dedup();
+        PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        overlap(self.0, &a.fingerprints, &b.fingerprints)
+    }
+}
+
+/// Several signals in one score, each catching what the others miss.
+///
+/// Levenshtein goes down when code is moved around but lines don't, lines
+/// goes down when names change but levenshtein only a little, and one long
+/// shared run of text is hard to get by accident. The score is their
+/// weighted mean.
+struct Confidence {
+    edit: eddie::str::Levenshtein,
+    lines: Lines,
+    /// One row of the longest shared run's table, kept between pairs
+    row: RefCell<Vec<u32>>,
+    weights: Weights,
+}
+
+impl Confidence {
+    /// The longest run of bytes in both, out of the shorter one's length
+    fn substring(&self, a: &[u8], b: &[u8]) -> f64 {
+        if a.is_empty() || b.is_empty() {
+            return if a.len() == b.len() { 1.0 } else { 0.0 };
+        }
+        let mut row = self.row.borrow_mut();
+        row.clear();
+        row.resize(b.len() + 1, 0);
+        let mut longest = 0;
+        for &x in a {
+            // right to left, so the diagonal is still last row's
+            for j in (0..b.len()).rev() {
+                row[j + 1] = if x == b[j] { row[j] + 1 } else { 0 };
+                longest = longest.max(row[j + 1]);
+            }
+        }
+        longest as f64 / a.len().min(b.len()) as f64
+    }
+}
+
+impl Similarity for Confidence {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        self.lines.prepare(text)
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        let Weights { edit, lines, substring } = self.weights;
+        let total = edit * self.edit.similarity(a.text, b.text)
+            + lines * self.lines.score(a, b)
+            + substring * self.substring(a.text.as_bytes(), b.text.as_bytes());
+        total / (edit + lines + substring)
+    }
+}
+
+/// Lines within this many characters of each other are the same length for --algorithm structure
+const STRUCTURE_LENGTH_BUCKET: usize = 8;
+
+/// Edit distance between the shapes of the files, one line at a time.
+///
+/// Each line is only its indentation and its length rounded off, so code
+/// laid out the same way scores high whatever it says. That's a lot of
+/// code, so it's a rough signal.
+struct Structure(eddie::slice::Levenshtein);
+
+impl Similarity for Structure {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        let fingerprints = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let content = line.trim_start();
+                let indent: usize = line[..line.len() - content.len()]
+                    .chars()
+                    .map(|c| if c == '\t' { 4 } else { 1 })
+                    .sum();
+                let length = content.trim_end().chars().count() / STRUCTURE_LENGTH_BUCKET;
+                ((indent as u64) << 32) | length as u64
+            })
+            .collect();
+        PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        self.0.similarity(&a.fingerprints, &b.fingerprints)
+    }
+}
+
+/// Shingles of tokens, each matching if one in the other file has at most
+/// `edits` tokens different. The score's the share of shingles from both
+/// files that found a match.
+struct FuzzyShingles(Shingling);
+
+/// Hashes each token, where a token's a run of letters, digits and underscores
+/// or any other one character that isn't whitespace
+fn token_hashes(text: &str) -> Vec<u64> {
+    let mut hashes = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let word = |c: char| c.is_alphanumeric() || c == '_';
+        let end = match rest.find(|c: char| !word(c)) {
+            Some(0) => rest.chars().next().map_or(rest.len(), char::len_utf8),
+            Some(end) => end,
+            None => rest.len(),
+        };
+        let mut hasher = DefaultHasher::new();
+        rest[..end].hash(&mut hasher);
+        hashes.push(hasher.finish());
+        rest = &rest[end..];
+    }
+    hashes
+}
+
+impl FuzzyShingles {
+    /// How many of `a`'s shingles have a close enough one in `b`
+    fn matched(&self, a: &[u64], b: &[u64], size: usize) -> usize {
+        let close = |x: &[u64], y: &[u64]| {
+            let mut different = 0;
+            for (x, y) in x.iter().zip(y) {
+                if x != y {
+                    different += 1;
+                    if different > self.0.edits {
+                        return false;
+                    }
+                }
+            }
+            true
+        };
+        a.windows(size).filter(|x| b.windows(size).any(|y| close(x, y))).count()
+    }
+}
+
+impl Similarity for FuzzyShingles {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        PreparedFile { text, fingerprints: token_hashes(text), variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        let (a, b) = (&a.fingerprints[..], &b.fingerprints[..]);
+        if a.is_empty() || b.is_empty() {
+            return if a.is_empty() && b.is_empty() { 1.0 } else { 0.0 };
+        }
+        // short files get shorter shingles, so there's at least one each
+        let size = self.0.size.min(a.len()).min(b.len());
+        let shingles = (a.len() - size + 1) + (b.len() - size + 1);
+        (self.matched(a, b, size) + self.matched(b, a, size)) as f64 / shingles as f64
+    }
+}
+
+/// How many tokens are in each of tf-idf's shingles
+const TFIDF_SHINGLE_TOKENS: usize = 5;
+
+/// How many of the files each tf-idf shingle is in, counted once before comparing
+pub struct Rarity {
+    files: usize,
+    counts: std::collections::HashMap<u64, usize>,
+}
+
+impl Rarity {
+    /// Smoothed so a shingle every file has still counts for something, and
+    /// one that isn't in any of them (like in a variant) counts the most
+    fn weight(&self, shingle: u64) -> f64 {
+        let count = self.counts.get(&shingle).copied().unwrap_or(0);
+        ((1 + self.files) as f64 / (1 + count) as f64).ln() + 1.0
+    }
+
+    /// Counts one more file, from what tf-idf prepared for it
+    pub fn add(&mut self, fingerprints: &[u64]) {
+        self.files += 1;
+        // sorted, so each one's only counted once per file
+        for shingle in distinct(fingerprints) {
+            *self.counts.entry(shingle).or_default() += 1;
+        }
+    }
+
+    /// Takes back an `add`, for a file that changed
+    pub fn remove(&mut self, fingerprints: &[u64]) {
+        self.files -= 1;
+        for shingle in distinct(fingerprints) {
+            if let Some(count) = self.counts.get_mut(&shingle) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&shingle);
+                }
+            }
+        }
+    }
+}
+
+/// Each of some sorted fingerprints once
+fn distinct(fingerprints: &[u64]) -> impl Iterator<Item = u64> + '_ {
+    fingerprints
+        .iter()
+        .enumerate()
+        .filter(|&(i, shingle)| i == 0 || fingerprints[i - 1] != *shingle)
+        .map(|(_, &shingle)| shingle)
+}
+
+/// Counts which files have each shingle, from what tf-idf prepared for every
+/// file. Only tf-idf needs it, so it's `None` for every other algorithm.
+pub fn count_rarity<'a>(algorithm: Algorithm, files: impl Iterator<Item = &'a PreparedFile<'a>>) -> Option<Arc<Rarity>> {
+    if algorithm != Algorithm::TfIdf {
+        return None;
+    }
+    let mut rarity = Rarity { files: 0, counts: std::collections::HashMap::new() };
+    for file in files {
+        rarity.add(&file.fingerprints);
+    }
+    Some(Arc::new(rarity))
+}
+
+/// Weighted jaccard over shingles, each shared one counting as many times
+/// as it's in both files and weighted by how rare it is
+struct TfIdf(Option<Arc<Rarity>>);
+
+impl Similarity for TfIdf {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        let tokens = token_hashes(text);
+        // short files are one shingle, so they still have something to compare
+        let mut fingerprints: Vec<u64> = tokens
+            .windows(TFIDF_SHINGLE_TOKENS.min(tokens.len()).max(1))
+            .map(|shingle| {
+                let mut hasher = DefaultHasher::new();
+                shingle.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        fingerprints.sort_unstable();
+        PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        let (a, b) = (&a.fingerprints[..], &b.fingerprints[..]);
+        if a.is_empty() && b.is_empty() {
+            return 1.0;
+        }
+        let weight = |shingle: u64| self.0.as_ref().map_or(1.0, |rarity| rarity.weight(shingle));
+        // how many of the same shingle are at the start of a sorted list
+        let run = |list: &[u64]| list.iter().take_while(|&&shingle| shingle == list[0]).count();
+        let (mut i, mut j) = (0, 0);
+        let (mut shared, mut either) = (0.0, 0.0);
+        while i < a.len() || j < b.len() {
+            let next = match (a.get(i), b.get(j)) {
+                (Some(&x), Some(&y)) => x.min(y),
+                (Some(&x), None) => x,
+                (None, Some(&y)) => y,
+                (None, None) => unreachable!("one of them has more"),
+            };
+            let in_a = if a.get(i) == Some(&next) { run(&a[i..]) } else { 0 };
+            let in_b = if b.get(j) == Some(&next) { run(&b[j..]) } else { 0 };
+            let weight = weight(next);
+            shared += in_a.min(in_b) as f64 * weight;
+            either += in_a.max(in_b) as f64 * weight;
+            i += in_a;
+            j += in_b;
+        }
+        shared / either
+    }
+}
+
+/// How many tokens are in each run winnowing hashes
+const WINNOWING_TOKENS: usize = 5;
+/// Winnowing keeps the smallest hash out of every this many runs in a row, so
+/// any copied stretch this many runs long (plus the run's length) is found
+const WINNOWING_WINDOW: usize = 4;
+
+/// Jaccard over the fingerprints winnowing picks out of each file
+struct Winnowing;
+
+impl Similarity for Winnowing {
+    fn prepare<'a>(&self, text: &'a str) -> PreparedFile<'a> {
+        let tokens = token_hashes(text);
+        let hashes: Vec<u64> = tokens
+            .windows(WINNOWING_TOKENS.min(tokens.len()).max(1))
+            .map(|run| {
+                let mut hasher = DefaultHasher::new();
+                run.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect();
+        // the rightmost smallest hash in every window, which neighboring windows
+        // mostly share, so the same text picks the same fingerprints anywhere
+        let mut fingerprints: Vec<u64> = hashes
+            .windows(WINNOWING_WINDOW.min(hashes.len()).max(1))
+            .filter_map(|window| window.iter().rev().min().copied())
+            .collect();
+        fingerprints.sort_unstable();
+        fingerprints.dedup();
+        PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+    }
+
+    fn score(&self, a: &PreparedFile, b: &PreparedFile) -> f64 {
+        overlap(Overlap::Jaccard, &a.fingerprints, &b.fingerprints)
+    }
+}
+
+/// How much two sorted sets overlap
+fn overlap(kind: Overlap, a: &[u64], b: &[u64]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    match kind {
+        Overlap::Jaccard => shared as f64 / (a.len() + b.len() - shared) as f64,
+        // an empty file is in everything, but that doesn't mean anything
+        Overlap::Containment => shared as f64 / a.len().min(b.len()).max(1) as f64,
+    }
+}
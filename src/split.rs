@@ -0,0 +1,223 @@
+//! Splits files into their top-level functions, so they can be compared one by one.
+//!
+//! This doesn't really parse anything, it just goes by indentation or braces,
+//! which is good enough for code that compiles.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// How to find where functions start and end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitLanguage {
+    /// Anything that isn't indented starts a new block
+    Python,
+    /// Blocks are whatever's between matching top-level braces
+    CLike,
+}
+
+impl FromStr for SplitLanguage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "python" | "py" => Ok(SplitLanguage::Python),
+            "c" | "cpp" | "c++" | "java" | "js" | "javascript" | "rust" | "go" | "c-like" => {
+                Ok(SplitLanguage::CLike)
+            }
+            _ => Err(format!(
+                "can't split \"{}\" into functions, supported: python, c, cpp, java, js, rust, go",
+                s
+            )),
+        }
+    }
+}
+
+/// Guesses a block's name from the identifier right before the first `(`
+fn block_name(header: &str) -> Option<&str> {
+    let mut before = header[..header.find('(')?].trim_end();
+    // generics go between the name and the arguments, like `fn f<T>(`
+    if before.ends_with('>') {
+        let mut depth = 0;
+        for (i, c) in before.char_indices().rev() {
+            match c {
+                '>' => depth += 1,
+                '<' => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                before = before[..i].trim_end();
+                break;
+            }
+        }
+    }
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    Some(&before[start..])
+}
+
+/// Follows the braces in a line of C-like code, leaving out any in strings,
+/// character literals and `//` comments
+fn count_braces(line: &str, depth: &mut usize) {
+    // braces are ascii, and no other character has ascii bytes in it
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' => *depth += 1,
+            b'}' => *depth = depth.saturating_sub(1),
+            b'/' if bytes.get(i + 1) == Some(&b'/') => break,
+            quote @ (b'"' | b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    // whatever's escaped can't end it, like a quote
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            // rust's lifetimes start with a quote too, but never end with one
+            b'\'' => {
+                let end = if bytes.get(i + 1) == Some(&b'\\') { i + 3 } else { i + 2 };
+                if bytes.get(end) == Some(&b'\'') {
+                    i = end;
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+}
+
+/// Splits some code into its top-level blocks, returning each one's name and text
+pub fn functions(language: SplitLanguage, code: &str) -> Vec<(String, String)> {
+    let mut blocks: Vec<(String, String)> = Vec::new();
+    let mut current = String::new();
+    match language {
+        SplitLanguage::Python => {
+            // whether the current block is a def/class, instead of loose top-level code
+            let mut in_definition = false;
+            for line in code.lines() {
+                let top_level = !line.starts_with(char::is_whitespace)
+                    && !line.trim().is_empty()
+                    && !line.starts_with('#');
+                if top_level {
+                    let definition = ["def ", "async def ", "class ", "@"]
+                        .iter()
+                        .any(|start| line.starts_with(start));
+                    // decorators belong to the function after them
+                    let decorating = current.lines().all(|line| line.starts_with('@'));
+                    let starts_block = (definition && !decorating) || (!definition && in_definition);
+                    if starts_block && !current.trim().is_empty() {
+                        blocks.push((String::new(), std::mem::take(&mut current)));
+                    }
+                    if starts_block || current.is_empty() {
+                        in_definition = definition;
+                    }
+                }
+                current.push_str(line);
+                current.push('\n');
+            }
+        }
+        SplitLanguage::CLike => {
+            let mut depth = 0usize;
+            for line in code.lines() {
+                current.push_str(line);
+                current.push('\n');
+                let was_inside = depth > 0;
+                count_braces(line, &mut depth);
+                // a block ends when the braces close back to the top level
+                if was_inside && depth == 0 {
+                    blocks.push((String::new(), std::mem::take(&mut current)));
+                }
+            }
+        }
+    }
+    if !current.trim().is_empty() {
+        blocks.push((String::new(), current));
+    }
+    // name the blocks, numbering any names that repeat
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (i, (name, text)) in blocks.iter_mut().enumerate() {
+        let base = match language {
+            SplitLanguage::Python => text
+                .lines()
+                .find(|line| !line.starts_with('@'))
+                .and_then(|line| {
+                    ["def ", "async def ", "class "]
+                        .iter()
+                        .find_map(|keyword| line.strip_prefix(keyword))
+                })
+                .map(|rest| {
+                    rest.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .next()
+                        .unwrap_or_default()
+                }),
+            // the signature is everything before the body starts
+            SplitLanguage::CLike => block_name(text.split('{').next().unwrap_or_default()),
+        };
+        let base = base
+            .filter(|name| !name.is_empty())
+            .map_or_else(|| format!("block{}", i + 1), str::to_string);
+        let count = seen.entry(base.clone()).or_default();
+        *count += 1;
+        *name = if *count == 1 { base } else { format!("{}#{}", base, count) };
+    }
+    blocks
+}
+
+/// Puts a file's top-level blocks in an order that only depends on what's in
+/// them, so files with the same functions in a different order come out the same
+pub fn sorted_blocks(language: SplitLanguage, code: &str) -> String {
+    let mut blocks: Vec<(u64, String)> = functions(language, code)
+        .into_iter()
+        .map(|(_, text)| {
+            // blank lines between blocks go with the one before, so the last block wouldn't have them
+            let text = format!("{}\n", text.trim_end());
+            let mut hasher = DefaultHasher::new();
+            text.hash(&mut hasher);
+            (hasher.finish(), text)
+        })
+        .collect();
+    // the text breaks ties, in the unlikely case two different blocks hash the same
+    blocks.sort_unstable();
+    blocks.into_iter().map(|(_, text)| text).collect()
+}
+
+#[cfg(test)]
+#[test]
+fn python_is_split_by_definition() {
+    let code = concat!(
+        "import os\n\n",
+        "@app.route(\"/\")\n@login\ndef view():\n    def inner():\n        return 1\n    return inner\n\n",
+        "class A:\n    def m(self):\n        pass\n",
+        "x = 1\n",
+        "def view():\n    pass\n",
+    );
+    let blocks = functions(SplitLanguage::Python, code);
+    let names: Vec<&str> = blocks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["block1", "view", "A", "block4", "view#2"]);
+    // decorators and nested defs stay with the function they're on or in
+    assert!(blocks[1].1.starts_with("@app.route(\"/\")\n@login\ndef view():\n    def inner():"));
+    assert!(blocks[1].1.ends_with("    return inner\n\n"));
+}
+
+#[cfg(test)]
+#[test]
+fn braces_in_strings_dont_count() {
+    let code = concat!(
+        "int main() {\n    printf(\"}{\");\n    char c = '}';\n    // }\n    return 0;\n}\n",
+        "fn f<'a>(x: &'a str) -> &'a str {\n    if x == \"{\" { x } else { \"\\\"}\" }\n}\n",
+    );
+    let blocks = functions(SplitLanguage::CLike, code);
+    let names: Vec<&str> = blocks.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["main", "f"]);
+    assert!(blocks[0].1.ends_with("return 0;\n}\n"));
+}
+
+#[cfg(test)]
+#[test]
+fn sorted_blocks_ignore_order() {
+    let a = "def f():\n    return 1\n\ndef g():\n    return 2\n";
+    let b = "def g():\n    return 2\n\n\ndef f():\n    return 1\n";
+    assert_eq!(sorted_blocks(SplitLanguage::Python, a), sorted_blocks(SplitLanguage::Python, b));
+}
@@ -0,0 +1,72 @@
+//! Keeps the results of every run in a SQLite database, for looking at
+//! across assignments and terms.
+//!
+//! ```sql
+//! runs  (id, started, algorithm, metric, sensitivity)
+//! files (run, path, grp)
+//! pairs (run, a, b, score)
+//! ```
+//!
+//! `started` is in seconds since 1970, `grp` is null for files that aren't in
+//! a group, and `score` is always a similarity, whatever --metric was.
+//! `sensitivity` is in the run's metric, like it was given.
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        started INTEGER NOT NULL,
+        algorithm TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        sensitivity REAL NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        run INTEGER NOT NULL REFERENCES runs(id),
+        path TEXT NOT NULL,
+        grp TEXT,
+        PRIMARY KEY (run, path)
+    );
+    CREATE TABLE IF NOT EXISTS pairs (
+        run INTEGER NOT NULL REFERENCES runs(id),
+        a TEXT NOT NULL,
+        b TEXT NOT NULL,
+        score REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS pairs_by_run ON pairs (run, score);
+";
+
+/// Adds a run to the database, creating it if needed, and returns the run's id
+pub fn write<'a>(
+    path: &Path,
+    files: impl Iterator<Item = (&'a PathBuf, Option<&'a Path>)>,
+    scores: &HashMap<(PathBuf, PathBuf), f64>,
+    algorithm: &str,
+    metric: &str,
+    sensitivity: f64,
+) -> rusqlite::Result<i64> {
+    let mut db = Connection::open(path)?;
+    db.execute_batch(SCHEMA)?;
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64);
+    // all or nothing, and much faster than a transaction per insert
+    let transaction = db.transaction()?;
+    transaction.execute(
+        "INSERT INTO runs (started, algorithm, metric, sensitivity) VALUES (?1, ?2, ?3, ?4)",
+        params![started, algorithm, metric, sensitivity],
+    )?;
+    let run = transaction.last_insert_rowid();
+    {
+        let mut insert = transaction.prepare("INSERT INTO files (run, path, grp) VALUES (?1, ?2, ?3)")?;
+        for (file, group) in files {
+            insert.execute(params![run, file.to_string_lossy(), group.map(|group| group.to_string_lossy())])?;
+        }
+        let mut insert = transaction.prepare("INSERT INTO pairs (run, a, b, score) VALUES (?1, ?2, ?3, ?4)")?;
+        for ((x, y), score) in scores {
+            insert.execute(params![run, x.to_string_lossy(), y.to_string_lossy(), score])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(run)
+}
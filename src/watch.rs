@@ -0,0 +1,154 @@
+//! Keeps running and compares new files as they show up in a directory.
+use crate::similarity::{count_rarity, PreparedFile};
+use cheat_checker::compare::{compare_all, Comparing};
+use crate::{group_key, load_file, CliArgs, Loading};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a file has to go without changing before we think it's done being written
+const QUIET_TIME: Duration = Duration::from_millis(500);
+
+/// What a file looked like last time, to tell if it changed without reading it
+fn stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// A prepared file, with the fingerprints worked out for it earlier taken from `fingerprints`
+fn lent<'a>(path: &Path, text: &'a str, fingerprints: &mut HashMap<PathBuf, Vec<u64>>) -> PreparedFile<'a> {
+    let fingerprints = std::mem::take(fingerprints.get_mut(path).expect("every file's been prepared"));
+    PreparedFile { text, fingerprints, variants: Vec::new(), parts: Vec::new() }
+}
+
+/// Keeps prepared files' fingerprints for `lent` to hand out again
+fn give_back(prepared: HashMap<PathBuf, PreparedFile>, fingerprints: &mut HashMap<PathBuf, Vec<u64>>) {
+    for (path, file) in prepared {
+        fingerprints.insert(path, file.fingerprints);
+    }
+}
+
+/// Watches `dir` forever, comparing each new or changed file against every file seen so far.
+///
+/// `initial` files are loaded first, without being compared against each other.
+pub fn watch(
+    dir: &Path,
+    initial: &[PathBuf],
+    opts: &CliArgs,
+    loading: &Loading,
+    sensitivity: f64,
+) -> anyhow::Result<()> {
+    let metric = opts.metric;
+    let max_similarity = opts.max_sensitivity.map_or(f64::INFINITY, |max| metric.convert(max));
+    let mut corpus: HashMap<PathBuf, String> = HashMap::new();
+    let existing = crate::filter_paths(&vec![dir.join("**").join("*")], &opts.ignore_file);
+    for path in initial.iter().chain(&existing) {
+        match load_file(path, opts, loading) {
+            Ok(Some(contents)) => {
+                corpus.insert(path.clone(), contents);
+            }
+            Ok(None) => (),
+            Err(err) => log::warn!("Couldn't load {}, skipping it. ({:#})", path.to_string_lossy(), err),
+        }
+    }
+    log::info!("Watching {} with {} files already seen.", dir.to_string_lossy(), corpus.len());
+    // what the algorithm worked out about each file, so a new one only has to prepare itself.
+    // prepared files borrow their text, so these get lent out to them for each new file
+    let scorer = opts.algorithm.scorer();
+    let prepared: HashMap<PathBuf, PreparedFile> =
+        corpus.iter().map(|(path, text)| (path.clone(), scorer.prepare(text))).collect();
+    // kept up to date as files change, instead of counting every file again
+    let mut rarity = count_rarity(opts.algorithm, prepared.values());
+    let mut fingerprints = HashMap::new();
+    give_back(prepared, &mut fingerprints);
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    // files that changed recently, and when they last did
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    // for --follow, how each file looked when last checked
+    let mut stamps: HashMap<PathBuf, Option<(u64, SystemTime)>> = HashMap::new();
+    if opts.follow {
+        stamps.extend(corpus.keys().map(|path| (path.clone(), stamp(path))));
+    }
+    let mut last_poll = Instant::now();
+    // never set, there's no stopping partway through one new file
+    let stop = AtomicBool::new(false);
+    loop {
+        match rx.recv_timeout(QUIET_TIME) {
+            Ok(Ok(event)) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    pending.insert(path, Instant::now());
+                }
+            }
+            Ok(Ok(_)) | Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Ok(Err(err)) => log::warn!("Error while watching. ({})", err),
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("stopped getting events"),
+        }
+        if opts.follow && last_poll.elapsed() >= QUIET_TIME {
+            last_poll = Instant::now();
+            for (path, last) in &mut stamps {
+                let now = stamp(path);
+                if now != *last {
+                    *last = now;
+                    pending.insert(path.clone(), Instant::now());
+                }
+            }
+        }
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= QUIET_TIME)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            pending.remove(&path);
+            let Some(path) = crate::without_ignored(crate::only_files(vec![path]), &opts.ignore_file).pop() else {
+                continue;
+            };
+            let contents = match load_file(&path, opts, loading) {
+                Ok(Some(contents)) => contents,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!("Couldn't load {}, skipping it. ({:#})", path.to_string_lossy(), err);
+                    continue;
+                }
+            };
+            let new = scorer.prepare(&contents).fingerprints;
+            if let Some(rarity) = &mut rarity {
+                let rarity = Arc::get_mut(rarity).expect("only watch has the rarity between files");
+                if let Some(old) = fingerprints.get(&path) {
+                    rarity.remove(old);
+                }
+                rarity.add(&new);
+            }
+            fingerprints.insert(path.clone(), new);
+            corpus.insert(path.clone(), contents);
+            if opts.follow {
+                stamps.insert(path.clone(), stamp(&path));
+            }
+            let workqueue: Vec<(&PathBuf, &PathBuf)> = corpus
+                .keys()
+                .filter(|other| **other != path)
+                .filter(|other| match (group_key(&path, opts), group_key(other, opts)) {
+                    (Some(a), Some(b)) => a != b,
+                    _ => true,
+                })
+                .map(|other| (&path, other))
+                .collect();
+            log::debug!("Comparing {} against {} files.", path.to_string_lossy(), workqueue.len());
+            let prepared: HashMap<PathBuf, PreparedFile> =
+                corpus.iter().map(|(path, text)| (path.clone(), lent(path, text, &mut fingerprints))).collect();
+            let comparing = Comparing::new(opts.algorithm, rarity.clone());
+            compare_all(workqueue, &prepared, &comparing, opts.jobs, &stop, |x, y, score| {
+                if score >= sensitivity && score <= max_similarity {
+                    println!("{:.6}\t{}\t{}", metric.convert(score), x.to_string_lossy(), y.to_string_lossy());
+                }
+            });
+            give_back(prepared, &mut fingerprints);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Scoring runs once per pair, so it shouldn't allocate once a scorer's warmed up.
+//! This is its own binary so counting allocations doesn't slow down every other test.
+
+mod counting;
+
+use cheat_checker::similarity::{Algorithm, Overlap, Shingling, Unit, Weights};
+use cheat_checker::split::SplitLanguage;
+
+#[global_allocator]
+static ALLOCATOR: counting::Counting = counting::Counting;
+
+#[test]
+fn scoring_reuses_buffers() {
+    let a = "for i in range(10):\n    print(i)\n".repeat(20);
+    let b = "for n in range(10):\n    print(n + 1)\n".repeat(20);
+    let units = [Unit::Chars, Unit::Bytes, Unit::Graphemes];
+    let overlaps = [Overlap::Jaccard, Overlap::Containment];
+    // damerau isn't here, eddie's keeps a BTreeMap that allocates nodes as it fills
+    let algorithms = units.map(Algorithm::Levenshtein).into_iter().chain(overlaps.map(Algorithm::Lines));
+    let others = [
+        Algorithm::Confidence(Weights::default()),
+        Algorithm::Structure,
+        Algorithm::SortedBlocks(SplitLanguage::Python),
+        Algorithm::FuzzyShingles(Shingling::default()),
+        Algorithm::TfIdf,
+        Algorithm::Winnowing,
+    ];
+    for algorithm in algorithms.chain(others) {
+        let scorer = algorithm.scorer();
+        let (a, b) = (scorer.prepare(&a), scorer.prepare(&b));
+        scorer.score(&a, &b);
+        let before = counting::allocations();
+        for _ in 0..100 {
+            scorer.score(&a, &b);
+        }
+        assert_eq!(counting::allocations(), before, "{:?} allocated while scoring", algorithm);
+    }
+}
@@ -0,0 +1,27 @@
+//! Running the checker over the fixtures, shared by the integration tests.
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// The fixtures every test runs over, 6 files so 15 pairs
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/determinism/*.py")
+}
+
+/// Runs the checker over the fixtures with a threshold nothing reaches, plus `args`
+pub fn status_with(args: &[&str]) -> ExitStatus {
+    Command::new(env!("CARGO_BIN_EXE_cheat_checker"))
+        .args(["--sensitivity", "2"])
+        .args(args)
+        .arg(fixtures())
+        .status()
+        .expect("couldn't run cheat_checker")
+}
+
+/// Same as `status_with`, but returns what it wrote to the logfile. `name` keeps each test's logfile apart.
+pub fn logfile_with(name: &str, args: &[&str]) -> String {
+    let logfile = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.csv", name));
+    let logfile_arg = logfile.to_string_lossy();
+    let args: Vec<&str> = args.iter().copied().chain(["--log", &logfile_arg]).collect();
+    assert!(status_with(&args).success());
+    std::fs::read_to_string(&logfile).expect("no logfile was written")
+}
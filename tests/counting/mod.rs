@@ -0,0 +1,28 @@
+//! A global allocator that counts allocations, for tests/allocations.rs and benches/scoring.rs.
+//! It replaces the allocator for a whole binary, which is why neither of those are unit tests.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+pub struct Counting;
+
+thread_local! {
+    // per thread, so other tests running at the same time don't count
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// How many allocations this thread has made so far
+pub fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
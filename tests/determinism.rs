@@ -0,0 +1,55 @@
+//! Threaded and single-threaded runs have to agree on every score.
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Runs the checker over the fixtures, returning what it wrote to the logfile
+fn logfile_with_jobs(jobs: usize) -> String {
+    common::logfile_with(&format!("determinism_{}", jobs), &["--jobs", &jobs.to_string()])
+}
+
+/// Runs the checker over the fixtures, returning the logfile parsed into a score map
+fn scores_with_jobs(jobs: usize) -> HashMap<(String, String), String> {
+    logfile_with_jobs(jobs)
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, ',');
+            let score = fields.next().unwrap().to_string();
+            let x = fields.next().unwrap().to_string();
+            let y = fields.next().unwrap().to_string();
+            ((x, y), score)
+        })
+        .collect()
+}
+
+#[test]
+fn serial_matches_parallel() {
+    let serial = scores_with_jobs(1);
+    let parallel = scores_with_jobs(4);
+    // 6 fixtures, so 15 pairs
+    assert_eq!(serial.len(), 15);
+    assert_eq!(serial, parallel);
+}
+
+#[test]
+fn logfile_is_well_formed_with_many_jobs() {
+    // way more threads than pairs, so workers finish at the same time a lot
+    let logfile = logfile_with_jobs(32);
+    let mut pairs = HashSet::new();
+    for line in logfile.lines() {
+        let fields: Vec<&str> = line.split(',').collect();
+        assert_eq!(fields.len(), 3, "bad line {:?}", line);
+        let score: f64 = fields[0].parse().expect("score isn't a number");
+        assert!((0.0..=1.0).contains(&score));
+        assert!(Path::new(fields[1]).is_file() && Path::new(fields[2]).is_file());
+        assert!(pairs.insert((fields[1], fields[2])), "{:?} was logged twice", line);
+    }
+    assert_eq!(pairs.len(), 15);
+    assert!(logfile.ends_with('\n'));
+}
+
+#[test]
+fn verify_serial_passes() {
+    assert!(common::status_with(&["--jobs", "4", "--verify-serial"]).success());
+}
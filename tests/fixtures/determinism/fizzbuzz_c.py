@@ -0,0 +1,10 @@
+def fizzbuzz(n):
+    out = ""
+    if n % 3 == 0:
+        out += "Fizz"
+    if n % 5 == 0:
+        out += "Buzz"
+    return out or str(n)
+
+for n in range(1, 101):
+    print(fizzbuzz(n))
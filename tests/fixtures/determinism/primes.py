@@ -0,0 +1,9 @@
+def is_prime(n):
+    if n < 2:
+        return False
+    for d in range(2, int(n ** 0.5) + 1):
+        if n % d == 0:
+            return False
+    return True
+
+print([n for n in range(100) if is_prime(n)])
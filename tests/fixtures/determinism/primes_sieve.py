@@ -0,0 +1,8 @@
+limit = 100
+sieve = [True] * limit
+sieve[0] = sieve[1] = False
+for i in range(2, limit):
+    if sieve[i]:
+        for j in range(i * i, limit, i):
+            sieve[j] = False
+print([i for i, prime in enumerate(sieve) if prime])
@@ -0,0 +1,46 @@
+//! --formatter runs once per file, and files it fails on are handled like --formatter-on-error says.
+#![cfg(unix)]
+mod common;
+
+use std::path::PathBuf;
+
+/// Runs the checker over the fixtures with a formatter, returning the logfile
+fn logfile_with_formatter(name: &str, formatter: &str, extra: &[&str]) -> String {
+    let args: Vec<&str> = ["--jobs", "4", "--formatter", formatter].into_iter().chain(extra.iter().copied()).collect();
+    common::logfile_with(&format!("formatter_{}", name), &args)
+}
+
+#[test]
+fn formatter_runs_once_per_file() {
+    let count = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("formatter_calls.txt");
+    let _ = std::fs::remove_file(&count);
+    let formatter = format!("echo \"$CHEAT_CHECKER_FILE\" >> '{}'; cat", count.display());
+    let logfile = logfile_with_formatter("counted", &formatter, &["--trim"]);
+    assert_eq!(logfile.lines().count(), 15);
+    // 6 fixtures, however many pairs they make
+    let calls = std::fs::read_to_string(&count).expect("the formatter never ran");
+    assert_eq!(calls.lines().count(), 6);
+}
+
+#[test]
+fn missing_formatter_falls_back() {
+    let logfile = logfile_with_formatter("missing", "cheat-checker-no-such-formatter", &[]);
+    assert_eq!(logfile.lines().count(), 15);
+}
+
+/// Fails on hello.py, formats everything else by leaving it alone
+const FAILS_ON_HELLO: &str = "case \"$CHEAT_CHECKER_FILE\" in *hello.py) exit 1;; esac; cat";
+
+#[test]
+fn formatter_failures_can_be_skipped() {
+    let logfile = logfile_with_formatter("skip", FAILS_ON_HELLO, &["--formatter-on-error", "skip"]);
+    // 5 fixtures left
+    assert_eq!(logfile.lines().count(), 10);
+    assert!(!logfile.contains("hello.py"));
+}
+
+#[test]
+fn formatter_failures_can_stop_the_run() {
+    let status = common::status_with(&["--formatter", FAILS_ON_HELLO, "--formatter-on-error", "fail"]);
+    assert_eq!(status.code(), Some(1));
+}